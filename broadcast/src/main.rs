@@ -1,12 +1,13 @@
 use maelstrom::{MessageID, NodeID};
 use std::{
-    collections::{hash_map::Entry, HashMap, HashSet},
-    time::{Duration, Instant},
+    collections::{btree_map::Entry, BTreeMap, HashMap, HashSet},
+    time::Duration,
 };
+use tokio::time::Instant;
+use tracing::warn;
 
-#[derive(Debug, PartialEq, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
-#[serde(tag = "type")]
-#[serde(rename_all = "snake_case")]
+#[maelstrom::payload]
+#[derive(Debug, PartialEq, Clone)]
 enum BroadcastPayload {
     Broadcast {
         message: u32,
@@ -38,8 +39,9 @@ struct Broadcast {
     /// receiving a message from a client.
     always_broadcast: bool,
 
-    neighbor_messages_not_acked: HashMap<NodeID, HashMap<Vec<u32>, AckContext>>,
-    batched_sends_to_neighbors: HashMap<NodeID, (Instant, Vec<u32>)>,
+    // BTreeMaps so that messages are sent in the same order on every run.
+    neighbor_messages_not_acked: BTreeMap<NodeID, BTreeMap<Vec<u32>, AckContext>>,
+    batched_sends_to_neighbors: BTreeMap<NodeID, (Instant, Vec<u32>)>,
 }
 
 impl Broadcast {
@@ -66,7 +68,7 @@ impl Broadcast {
         match self
             .neighbor_messages_not_acked
             .entry(neighbor)
-            .or_insert_with(HashMap::new)
+            .or_default()
             .entry(messages)
         {
             Entry::Occupied(mut entry) => {
@@ -122,7 +124,7 @@ impl maelstrom::App for Broadcast {
 
         Self {
             messages_seen: HashSet::new(),
-            neighbor_messages_not_acked: HashMap::new(),
+            neighbor_messages_not_acked: BTreeMap::new(),
             // Don't want to include self in neighbors.
             neighbors: neighbors
                 .iter()
@@ -130,7 +132,7 @@ impl maelstrom::App for Broadcast {
                 .cloned()
                 .collect(),
             always_broadcast,
-            batched_sends_to_neighbors: HashMap::new(),
+            batched_sends_to_neighbors: BTreeMap::new(),
         }
     }
 
@@ -162,7 +164,7 @@ impl maelstrom::App for Broadcast {
                 let neighbor_messages = self
                     .neighbor_messages_not_acked
                     .entry(message.src)
-                    .or_insert_with(HashMap::new);
+                    .or_default();
                 let mut message_found = None;
                 for (message_key, ack_context) in neighbor_messages.iter_mut() {
                     if message.body.in_reply_to == Some(ack_context.message_id) {
@@ -190,7 +192,7 @@ impl maelstrom::App for Broadcast {
                 writer.reply_to(&message, BroadcastPayload::TopologyOk)?;
             }
             _ => {
-                eprintln!("Ignoring non-relevant payload: {message:?}.");
+                warn!(?message, "Ignoring non-relevant payload.");
                 return Ok(());
             }
         }
@@ -198,6 +200,11 @@ impl maelstrom::App for Broadcast {
         Ok(())
     }
 
+    fn tick_interval(&self) -> Duration {
+        // Neighbor sends are batched for 100ms, no need to check more often.
+        Duration::from_millis(50)
+    }
+
     async fn tick(&mut self, writer: &maelstrom::MessageWriter) -> anyhow::Result<()> {
         let mut keys_to_remove = vec![];
         for (neighbor, (start_time, messages)) in self.batched_sends_to_neighbors.clone() {
@@ -226,9 +233,128 @@ impl maelstrom::App for Broadcast {
         }
         Ok(())
     }
+
+    async fn shutdown(&mut self, writer: &maelstrom::MessageWriter) -> anyhow::Result<()> {
+        // Don't wait out the batching window, there won't be another tick.
+        for (neighbor, (_, messages)) in std::mem::take(&mut self.batched_sends_to_neighbors) {
+            self.batched_send_to_neighbor(writer, neighbor, messages)?;
+        }
+        Ok(())
+    }
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     maelstrom::event_loop::<Broadcast, BroadcastPayload>().await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maelstrom::checker::check_set_convergence;
+    use maelstrom::sim::{LatencyDistribution, NetworkConditions, SimConfig, Simulation};
+
+    #[test]
+    fn broadcasts_reach_every_node() -> anyhow::Result<()> {
+        let config = SimConfig {
+            node_count: 10,
+            seed: 7,
+            ..Default::default()
+        };
+        Simulation::<Broadcast>::run(config, |sim| async move {
+            let client = sim.client();
+            let node_ids = sim.node_ids().to_vec();
+            for (index, message) in (0..20).enumerate() {
+                let node_id = &node_ids[index % node_ids.len()];
+                let response = client
+                    .call(node_id, BroadcastPayload::Broadcast { message })
+                    .await?;
+                assert_eq!(response, BroadcastPayload::BroadcastOk);
+            }
+
+            tokio::time::sleep(Duration::from_secs(2)).await;
+
+            for node_id in &node_ids {
+                let BroadcastPayload::ReadOk { messages } =
+                    client.call(node_id, BroadcastPayload::Read).await?
+                else {
+                    anyhow::bail!("Expected ReadOk in response to Read.");
+                };
+                let messages = messages.into_iter().collect::<HashSet<_>>();
+                assert_eq!(messages, (0..20).collect(), "{node_id:?} missed messages");
+            }
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn batched_broadcasts_are_sent_on_shutdown() -> anyhow::Result<()> {
+        let config = SimConfig {
+            node_count: 10,
+            ..Default::default()
+        };
+        Simulation::<Broadcast>::run(config, |sim| async move {
+            let client = sim.client();
+            let node_ids = sim.node_ids().to_vec();
+            let stopped_node_id = &node_ids[1];
+            client
+                .call(stopped_node_id, BroadcastPayload::Broadcast { message: 1 })
+                .await?;
+            sim.shutdown_node(stopped_node_id);
+
+            tokio::time::sleep(Duration::from_secs(2)).await;
+
+            for node_id in node_ids.iter().filter(|id| *id != stopped_node_id) {
+                let response = client.call(node_id, BroadcastPayload::Read).await?;
+                assert_eq!(response, BroadcastPayload::ReadOk { messages: vec![1] });
+            }
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn broadcasts_survive_partitions_and_lossy_links() -> anyhow::Result<()> {
+        let config = SimConfig {
+            node_count: 10,
+            seed: 3,
+            network: NetworkConditions {
+                latency: LatencyDistribution::Exponential {
+                    mean: Duration::from_millis(20),
+                },
+                drop_probability: 0.2,
+                duplicate_probability: 0.1,
+            },
+            ..Default::default()
+        };
+        Simulation::<Broadcast>::run(config, |sim| async move {
+            let client = sim.client();
+            let node_ids = sim.node_ids().to_vec();
+            let nemesis = sim.spawn_partition_nemesis(Duration::from_millis(300));
+            for (index, message) in (0..20).enumerate() {
+                let node_id = &node_ids[index % node_ids.len()];
+                client
+                    .call(node_id, BroadcastPayload::Broadcast { message })
+                    .await?;
+            }
+
+            nemesis.abort();
+            sim.heal();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+
+            for node_id in &node_ids {
+                client.call(node_id, BroadcastPayload::Read).await?;
+            }
+            check_set_convergence(
+                &sim.history(),
+                |request| match request {
+                    BroadcastPayload::Broadcast { message } => Some(*message),
+                    _ => None,
+                },
+                |response| match response {
+                    BroadcastPayload::ReadOk { messages } => Some(messages.clone()),
+                    _ => None,
+                },
+            )
+        })
+    }
+}
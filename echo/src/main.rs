@@ -1,6 +1,7 @@
-#[derive(Debug, PartialEq, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
-#[serde(tag = "type")]
-#[serde(rename_all = "snake_case")]
+use tracing::warn;
+
+#[maelstrom::payload]
+#[derive(Debug, PartialEq, Clone)]
 enum EchoPayload {
     Echo { echo: String },
     EchoOk { echo: String },
@@ -26,7 +27,7 @@ impl maelstrom::App for Echo {
                 writer.reply_to(&message, EchoPayload::EchoOk { echo: echo.clone() })?;
             }
             _ => {
-                eprintln!("Ignoring non-relevant payload: {message:?}.");
+                warn!(?message, "Ignoring non-relevant payload.");
                 return Ok(());
             }
         }
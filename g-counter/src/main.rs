@@ -1,9 +1,12 @@
 use maelstrom::*;
-use std::time::{Duration, Instant};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::warn;
 
-#[derive(Debug, PartialEq, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
-#[serde(tag = "type")]
-#[serde(rename_all = "snake_case")]
+#[maelstrom::payload]
+#[derive(Debug, PartialEq, Clone)]
 enum Payload {
     Add { delta: u32 },
     AddOk,
@@ -11,76 +14,274 @@ enum Payload {
     ReadOk { value: u32 },
 }
 
-struct GCounter {
+/// The key-value service the counter is kept in, chosen with the
+/// `G_COUNTER_KV` environment variable: "seq-kv" (the default) or "lin-kv".
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backend {
+    SeqKv,
+    LinKv,
+}
+
+impl Backend {
+    const ENV_VAR: &'static str = "G_COUNTER_KV";
+
+    fn from_env() -> anyhow::Result<Self> {
+        Self::from_name(std::env::var(Self::ENV_VAR).ok().as_deref())
+    }
+
+    fn from_name(name: Option<&str>) -> anyhow::Result<Self> {
+        match name {
+            None | Some("seq-kv") => Ok(Backend::SeqKv),
+            Some("lin-kv") => Ok(Backend::LinKv),
+            Some(name) => anyhow::bail!("Unknown {} {name:?}.", Self::ENV_VAR),
+        }
+    }
+}
+
+/// The counter is kept in the store as every node's own total, so a flush
+/// sets this node's entry instead of adding to the counter. Retrying a flush
+/// that may have taken effect (e.g. one that timed out) can't count an add
+/// twice.
+type Totals = BTreeMap<String, u32>;
+
+struct State {
     last_read_time: Instant,
+    /// The sum of every node's total, as of the last read.
     last_read: u32,
-    unconfirmed_delta: u32,
+    /// This node's total in the store, as of the last read.
+    confirmed: u32,
+    /// Every add this node acknowledged.
+    added: u32,
+}
+
+impl State {
+    fn value(&self) -> u32 {
+        self.last_read + self.added - self.confirmed
+    }
+
+    fn unflushed(&self) -> bool {
+        self.added > self.confirmed
+    }
+}
+
+/// Handles messages concurrently so that adds and reads don't wait on the
+/// key-value store round-trips made by `tick`.
+struct GCounter {
+    node_id: maelstrom::NodeID,
+    backend: Backend,
+    state: Mutex<State>,
 }
 
 #[async_trait::async_trait]
-impl maelstrom::App for GCounter {
+impl maelstrom::ConcurrentApp for GCounter {
     type Payload = Payload;
 
-    fn new(_node_id: maelstrom::NodeID, _node_ids: Vec<maelstrom::NodeID>) -> Self {
+    fn new(node_id: maelstrom::NodeID, _node_ids: Vec<maelstrom::NodeID>) -> Self {
         Self {
-            last_read_time: Instant::now(),
-            last_read: 0,
-            unconfirmed_delta: 0,
+            node_id,
+            backend: Backend::from_env().expect("checked by main"),
+            state: Mutex::new(State {
+                last_read_time: Instant::now(),
+                last_read: 0,
+                confirmed: 0,
+                added: 0,
+            }),
         }
     }
 
     async fn handle(
-        &mut self,
+        self: Arc<Self>,
         message: maelstrom::Message<Self::Payload>,
         writer: &maelstrom::MessageWriter,
     ) -> Result<(), anyhow::Error> {
         match message.body.payload {
             Payload::Add { delta } => {
-                self.unconfirmed_delta += delta;
+                self.state.lock().expect("not poisoned").added += delta;
                 writer.reply_to(&message, Payload::AddOk)?;
             }
             Payload::Read => {
-                writer.reply_to(
-                    &message,
-                    Payload::ReadOk {
-                        value: self.last_read + self.unconfirmed_delta,
-                    },
-                )?;
+                let value = self.state.lock().expect("not poisoned").value();
+                writer.reply_to(&message, Payload::ReadOk { value })?;
             }
             _ => {
-                eprintln!("Ignoring non-relevant payload: {message:?}.");
+                warn!(?message, "Ignoring non-relevant payload.");
                 return Ok(());
             }
         }
         Ok(())
     }
 
-    async fn tick(&mut self, writer: &maelstrom::MessageWriter) -> anyhow::Result<()> {
-        let kv = SeqKV::new(writer);
-
-        if self.unconfirmed_delta > 0 {
-            self.last_read = kv.read("counter").await?.unwrap_or_default();
-            let swap_succeeded = kv
-                .compare_and_swap(
-                    "counter",
-                    self.last_read,
-                    self.last_read + self.unconfirmed_delta,
-                )
-                .await?;
-            if swap_succeeded {
-                self.last_read = self.last_read + self.unconfirmed_delta;
-                self.last_read_time = Instant::now();
-                self.unconfirmed_delta = 0;
+    fn tick_interval(&self) -> Duration {
+        // Flush deltas to the store as soon as possible.
+        Duration::from_millis(1)
+    }
+
+    async fn tick(self: Arc<Self>, writer: &maelstrom::MessageWriter) -> anyhow::Result<()> {
+        match self.backend {
+            Backend::SeqKv => self.tick_with(&SeqKV::new(writer)).await,
+            Backend::LinKv => self.tick_with(&LinKV::new(writer)).await,
+        }
+    }
+
+    async fn shutdown(self: Arc<Self>, writer: &maelstrom::MessageWriter) -> anyhow::Result<()> {
+        match self.backend {
+            Backend::SeqKv => self.flush_all(&SeqKV::new(writer)).await,
+            Backend::LinKv => self.flush_all(&LinKV::new(writer)).await,
+        }
+    }
+}
+
+impl GCounter {
+    async fn tick_with(&self, kv: &impl KeyValueStore) -> anyhow::Result<()> {
+        let (unflushed, last_read_time) = {
+            let state = self.state.lock().expect("not poisoned");
+            (state.unflushed(), state.last_read_time)
+        };
+
+        let result = if unflushed {
+            self.flush(kv).await
+        } else if last_read_time.elapsed() >= Duration::from_millis(500) {
+            self.refresh(kv).await
+        } else {
+            Ok(())
+        };
+        // Flushing is idempotent, so whatever went wrong (even a flush that may
+        // have gone through) is fixed by trying again on the next tick.
+        if let Err(error) = result {
+            warn!("Failed to sync with the store, retrying: {error}.");
+        }
+        Ok(())
+    }
+
+    async fn flush_all(&self, kv: &impl KeyValueStore) -> anyhow::Result<()> {
+        // Adds we acknowledged are lost unless they make it to the store.
+        while self.state.lock().expect("not poisoned").unflushed() {
+            match self.flush(kv).await {
+                Err(error) if error.is_transient() || error.is_indefinite() => {
+                    warn!("Failed to flush, retrying: {error}.");
+                }
+                result => result?,
             }
-        } else if self.last_read_time.elapsed() >= Duration::from_millis(500) {
-            self.last_read = kv.read("counter").await?.unwrap_or_default();
-            self.last_read_time = Instant::now();
         }
         Ok(())
     }
+
+    /// Sets this node's total in the store to every add it acknowledged.
+    async fn flush(&self, kv: &impl KeyValueStore) -> Result<(), KvError> {
+        // Adds can arrive while we wait on the store, so only the total seen
+        // here gets written.
+        let added = self.state.lock().expect("not poisoned").added;
+        let totals = kv
+            .update("counter", |totals: Option<&Totals>| {
+                let mut totals = totals.cloned().unwrap_or_default();
+                totals.insert(self.node_id.to_string(), added);
+                totals
+            })
+            .await?;
+        self.record_read(&totals);
+        Ok(())
+    }
+
+    async fn refresh(&self, kv: &impl KeyValueStore) -> Result<(), KvError> {
+        // seq-kv may serve a stale read, which would make reads on this node
+        // go backwards.
+        let totals = kv.fresh_read("counter").await?.unwrap_or_default();
+        self.record_read(&totals);
+        Ok(())
+    }
+
+    fn record_read(&self, totals: &Totals) {
+        let mut state = self.state.lock().expect("not poisoned");
+        state.last_read = totals.values().sum();
+        state.confirmed = totals
+            .get(self.node_id.as_str())
+            .copied()
+            .unwrap_or_default();
+        state.last_read_time = Instant::now();
+    }
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    maelstrom::event_loop::<GCounter, Payload>().await
+    Backend::from_env()?;
+    maelstrom::event_loop::<Concurrent<GCounter>, Payload>().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maelstrom::sim::{SimConfig, Simulation};
+
+    #[tokio::test]
+    async fn flushed_deltas_add_up_in_the_store() -> anyhow::Result<()> {
+        let kv = MemoryKV::new();
+        let counters = [
+            GCounter::new("n0".into(), vec![]),
+            GCounter::new("n1".into(), vec![]),
+        ];
+        for (counter, delta) in counters.iter().zip([3, 4]) {
+            counter.state.lock().unwrap().added = delta;
+            counter.flush(&kv).await?;
+        }
+        counters[0].refresh(&kv).await?;
+
+        let totals = kv.read::<_, Totals>("counter").await?.unwrap_or_default();
+        assert_eq!(totals.values().sum::<u32>(), 7);
+        let state = counters[0].state.lock().unwrap();
+        assert_eq!((state.last_read, state.confirmed, state.value()), (7, 3, 7));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retried_flushes_dont_count_adds_twice() -> anyhow::Result<()> {
+        let kv = MemoryKV::new();
+        let counter = GCounter::new("n0".into(), vec![]);
+        counter.state.lock().unwrap().added = 5;
+        // As if the first flush went through but its reply was lost.
+        counter.flush(&kv).await?;
+        counter.state.lock().unwrap().confirmed = 0;
+        counter.flush(&kv).await?;
+
+        assert_eq!(counter.state.lock().unwrap().value(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn backends_are_chosen_by_name() {
+        assert_eq!(Backend::from_name(None).unwrap(), Backend::SeqKv);
+        assert_eq!(Backend::from_name(Some("lin-kv")).unwrap(), Backend::LinKv);
+        assert!(Backend::from_name(Some("lww-kv")).is_err());
+    }
+
+    #[test]
+    fn every_node_converges_on_the_total() -> anyhow::Result<()> {
+        Simulation::<Concurrent<GCounter>>::run(SimConfig::default(), |sim| async move {
+            let client = sim.client();
+            let node_ids = sim.node_ids().to_vec();
+            for (index, delta) in (1..=10).enumerate() {
+                let node_id = &node_ids[index % node_ids.len()];
+                assert_eq!(
+                    client.call(node_id, Payload::Add { delta }).await?,
+                    Payload::AddOk
+                );
+            }
+
+            tokio::time::sleep(Duration::from_secs(2)).await;
+
+            for node_id in &node_ids {
+                client.call(node_id, Payload::Read).await?;
+            }
+            checker::check_counter(
+                &sim.history(),
+                |request| match request {
+                    Payload::Add { delta } => Some(u64::from(*delta)),
+                    _ => None,
+                },
+                |response| match response {
+                    Payload::ReadOk { value } => Some(u64::from(*value)),
+                    _ => None,
+                },
+            )
+        })
+    }
 }
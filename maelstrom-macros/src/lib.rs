@@ -0,0 +1,184 @@
+//! Macros for the `maelstrom` crate, use them through its re-exports (e.g.
+//! `#[maelstrom::payload]`).
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::punctuated::Punctuated;
+use syn::{parse_macro_input, Data, DeriveInput, Expr, ExprLit, Lit, Meta, Token};
+
+/// Turns an enum into a Maelstrom payload type, see `maelstrom::payload`.
+#[proc_macro_attribute]
+pub fn payload(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return syn::Error::new(
+            Span::call_site(),
+            "#[maelstrom::payload] takes no arguments",
+        )
+        .to_compile_error()
+        .into();
+    }
+    let input = parse_macro_input!(item as DeriveInput);
+    expand_payload(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_payload(mut input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "Maelstrom payloads can't be generic",
+        ));
+    }
+    let name = input.ident.clone();
+    let Data::Enum(data) = &mut input.data else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "#[maelstrom::payload] only supports enums",
+        ));
+    };
+
+    if !data.variants.iter().any(|variant| variant.ident == "Error") {
+        data.variants.push(syn::parse_quote! {
+            Error {
+                code: ::maelstrom::MaelstromErrorCode,
+                text: ::std::string::String,
+            }
+        });
+    }
+
+    let mut types = Vec::new();
+    for variant in &data.variants {
+        types.push((variant.ident.clone(), payload_type(variant)?));
+    }
+    let type_of = |ident: &syn::Ident| {
+        types
+            .iter()
+            .find(|(variant, _)| variant == ident)
+            .map(|(_, payload_type)| payload_type.clone())
+    };
+
+    let mut requests = Vec::new();
+    let mut responses = Vec::new();
+    for (variant, request_type) in &types {
+        if variant == "Error" {
+            responses.push(variant);
+            continue;
+        }
+        if let Some(request) = variant.to_string().strip_suffix("Ok") {
+            if request.is_empty() {
+                return Err(syn::Error::new_spanned(
+                    variant,
+                    "`Ok` isn't a reply to any request, name it after one (e.g. `EchoOk`)",
+                ));
+            }
+            if type_of(&format_ident!("{request}")).is_none() {
+                return Err(syn::Error::new_spanned(
+                    variant,
+                    format!("`{variant}` has no `{request}` request to reply to"),
+                ));
+            }
+            responses.push(variant);
+            continue;
+        }
+        let reply_type = match type_of(&format_ident!("{variant}Ok")) {
+            Some(reply_type) => quote!(::std::option::Option::Some(#reply_type)),
+            None => quote!(::std::option::Option::None),
+        };
+        requests.push(quote!((#request_type, #reply_type)));
+    }
+    let (variants, payload_types): (Vec<_>, Vec<_>) = types.iter().cloned().unzip();
+
+    Ok(quote! {
+        #[derive(
+            ::maelstrom::__private::serde_derive::Serialize,
+            ::maelstrom::__private::serde_derive::Deserialize,
+        )]
+        #[serde(crate = "::maelstrom::__private::serde")]
+        #[serde(tag = "type")]
+        #[serde(rename_all = "snake_case")]
+        #input
+
+        impl ::maelstrom::PayloadType for #name {
+            fn payload_type(&self) -> &'static str {
+                match self {
+                    #(#name::#variants { .. } => #payload_types,)*
+                }
+            }
+        }
+
+        impl ::maelstrom::MaelstromPayload for #name {
+            const REQUESTS: &'static [(&'static str, ::std::option::Option<&'static str>)] =
+                &[#(#requests),*];
+
+            fn is_response(&self) -> bool {
+                matches!(self, #(#name::#responses { .. })|*)
+            }
+        }
+    })
+}
+
+/// The variant's `type`: its `#[serde(rename = "...")]` if it has one, its
+/// name in snake_case otherwise (like serde's `rename_all = "snake_case"`).
+fn payload_type(variant: &syn::Variant) -> syn::Result<String> {
+    for attr in &variant.attrs {
+        if !attr.path().is_ident("serde") {
+            continue;
+        }
+        let metas = attr.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)?;
+        for meta in metas {
+            let Meta::NameValue(name_value) = meta else {
+                continue;
+            };
+            if !name_value.path.is_ident("rename") {
+                continue;
+            }
+            if let Expr::Lit(ExprLit {
+                lit: Lit::Str(rename),
+                ..
+            }) = &name_value.value
+            {
+                return Ok(rename.value());
+            }
+            return Err(syn::Error::new_spanned(
+                &name_value.value,
+                "expected a string literal",
+            ));
+        }
+    }
+
+    let mut snake_case = String::new();
+    for (index, char) in variant.ident.to_string().char_indices() {
+        if index > 0 && char.is_uppercase() {
+            snake_case.push('_');
+        }
+        snake_case.push(char.to_ascii_lowercase());
+    }
+    Ok(snake_case)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replies_must_name_their_request() {
+        let error = expand_payload(syn::parse_quote! {
+            enum Payload {
+                Echo,
+                Ok,
+            }
+        })
+        .unwrap_err();
+        assert!(error.to_string().contains("`Ok` isn't a reply"), "{error}");
+
+        let error = expand_payload(syn::parse_quote! {
+            enum Payload {
+                EchoOk,
+            }
+        })
+        .unwrap_err();
+        assert!(error.to_string().contains("no `Echo` request"), "{error}");
+    }
+}
@@ -1,103 +1,502 @@
 use anyhow::Context;
+use futures::FutureExt;
+use rand::{rngs::StdRng, SeedableRng};
 use serde::de::DeserializeOwned;
 use serde::ser::Serialize;
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, Semaphore};
 use tokio::task::JoinHandle;
 
 use crate::protocol::*;
-use std::collections::HashMap;
-use std::fmt::Debug;
-use std::io::{self, BufRead, Write};
+use crate::{MaelstromPayload, Metrics, PayloadType};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::{self, Debug};
+use std::future::Future;
+use std::io::{self, BufRead};
+use std::panic::AssertUnwindSafe;
 use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::Arc;
-use std::time::{Duration, Instant};
-use tokio::sync::mpsc::{self, UnboundedSender};
-use tokio::time::timeout;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TrySendError, UnboundedReceiver, UnboundedSender};
+use tokio::time::{interval, sleep, sleep_until, timeout, timeout_at, Instant, MissedTickBehavior};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
+mod concurrent;
+mod output;
+mod queues;
+mod retry;
+mod rpc;
 mod services;
+mod timers;
+pub use concurrent::*;
+pub(crate) use output::*;
+pub use queues::*;
+pub use retry::*;
+pub use rpc::*;
 pub use services::*;
+use timers::*;
+
+/// How long a node waits for its app to finish handling messages and shut
+/// down, see `run_node`.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+/// How many timed out RPCs to remember so that their late replies can be
+/// dropped instead of being handed to the app.
+const EXPIRED_RESPONSE_CALLBACKS_TO_REMEMBER: usize = 1024;
+/// The shortest tick interval, see `App::tick_interval`.
+const MIN_TICK_INTERVAL: Duration = Duration::from_millis(1);
+
+#[derive(Debug)]
+pub(crate) enum ResponseCallbackCommand {
+    Register(MessageID, oneshot::Sender<RawMessage>),
+    /// The RPC timed out and nobody is waiting on the response anymore.
+    Cancel(MessageID),
+}
+
+/// Hands responses to the RPCs waiting on them.
+#[derive(Default)]
+pub(crate) struct ResponseRouter {
+    response_callbacks: HashMap<MessageID, oneshot::Sender<RawMessage>>,
+    /// The RPCs that timed out, oldest first, and the same IDs as a set to look
+    /// up every response no RPC is waiting on.
+    expired_response_callbacks: VecDeque<MessageID>,
+    expired_response_callback_ids: HashSet<MessageID>,
+}
+
+impl ResponseRouter {
+    /// Registers / cancels the RPCs the writer has sent so far.
+    pub(crate) fn process_commands(
+        &mut self,
+        response_callback_receiver: &mut UnboundedReceiver<ResponseCallbackCommand>,
+    ) {
+        while let Ok(command) = response_callback_receiver.try_recv() {
+            match command {
+                ResponseCallbackCommand::Register(message_id, response_callback) => {
+                    let previous_value = self
+                        .response_callbacks
+                        .insert(message_id, response_callback);
+                    assert!(
+                        previous_value.is_none(),
+                        "Received multiple response callbacks for same message id, programmer error?"
+                    );
+                }
+                ResponseCallbackCommand::Cancel(message_id) => {
+                    if self.response_callbacks.remove(&message_id).is_some() {
+                        if self.expired_response_callbacks.len()
+                            >= EXPIRED_RESPONSE_CALLBACKS_TO_REMEMBER
+                        {
+                            if let Some(forgotten) = self.expired_response_callbacks.pop_front() {
+                                self.expired_response_callback_ids.remove(&forgotten);
+                            }
+                        }
+                        self.expired_response_callbacks.push_back(message_id);
+                        self.expired_response_callback_ids.insert(message_id);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the message back if no RPC was (or is still) waiting on it.
+    pub(crate) fn route(&mut self, message: RawMessage) -> Option<RawMessage> {
+        let Some(in_reply_to) = message.in_reply_to else {
+            return Some(message);
+        };
+        if let Some(response_callback) = self.response_callbacks.remove(&in_reply_to) {
+            if response_callback.send(message).is_err() {
+                debug!(
+                    in_reply_to = *in_reply_to,
+                    "Dropping response, RPC no longer waiting."
+                );
+            }
+            return None;
+        }
+        // Left in `expired_response_callbacks` until it's pushed out, message
+        // IDs aren't reused.
+        if self.expired_response_callback_ids.remove(&in_reply_to) {
+            debug!(
+                in_reply_to = *in_reply_to,
+                "Dropping late response to timed out RPC."
+            );
+            return None;
+        }
+        Some(message)
+    }
+}
+
+/// Returned (wrapped in an `anyhow::Error`) when an RPC does not get a response
+/// before its deadline. Use `error.is::<RpcTimeoutError>()` to tell it apart
+/// from other failures.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcTimeoutError {
+    pub node_id: NodeID,
+    pub message_id: MessageID,
+    pub timeout: Duration,
+}
+
+impl fmt::Display for RpcTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "RPC {:?} to {:?} timed out after {:?}",
+            self.message_id, self.node_id, self.timeout
+        )
+    }
+}
+
+impl std::error::Error for RpcTimeoutError {}
 
 #[derive(Debug, Clone)]
 pub struct MessageWriter {
     msg_id: Arc<AtomicU32>,
-    msg_sender: UnboundedSender<String>,
-    response_callback_sender:
-        UnboundedSender<(MessageID, oneshot::Sender<Message<serde_json::Value>>)>,
+    msg_sender: OutgoingSender,
+    response_callback_sender: UnboundedSender<ResponseCallbackCommand>,
+    timer_sender: UnboundedSender<TimerCommand>,
     node_id: NodeID,
+    metrics: Arc<Metrics>,
+    /// Jitters retries, seeded in simulations so that they're reproducible.
+    rng: Arc<Mutex<StdRng>>,
 }
 
 impl MessageWriter {
-    fn write_message<TPayload: Debug + Serialize>(
+    pub(crate) fn new(
+        node_id: NodeID,
+        msg_sender: OutgoingSender,
+        metrics: Arc<Metrics>,
+        rng: StdRng,
+    ) -> (
+        Self,
+        UnboundedReceiver<ResponseCallbackCommand>,
+        UnboundedReceiver<TimerCommand>,
+    ) {
+        let (response_callback_sender, response_callback_receiver) = mpsc::unbounded_channel();
+        let (timer_sender, timer_receiver) = mpsc::unbounded_channel();
+        let writer = Self {
+            msg_id: Arc::new(AtomicU32::new(0)),
+            msg_sender,
+            response_callback_sender,
+            timer_sender,
+            node_id,
+            metrics,
+            rng: Arc::new(Mutex::new(rng)),
+        };
+        (writer, response_callback_receiver, timer_receiver)
+    }
+
+    pub fn node_id(&self) -> &NodeID {
+        &self.node_id
+    }
+
+    /// What the runtime has measured about this node so far.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// How long to back off before attempt number `attempt + 1` of `policy`,
+    /// jitter included.
+    pub fn backoff(&self, policy: &RetryPolicy, attempt: u32) -> Duration {
+        let mut rng = self.rng.lock().expect("not poisoned");
+        policy.backoff_with_jitter(attempt, &mut *rng)
+    }
+
+    /// Calls `App::timer` with `name` once `delay` has passed. Scheduling a
+    /// timer that is still pending moves it to the new deadline.
+    pub fn schedule_timer(&self, name: impl Into<String>, delay: Duration) -> anyhow::Result<()> {
+        self.timer_sender
+            .send(TimerCommand::Schedule {
+                name: name.into(),
+                deadline: Instant::now() + delay,
+            })
+            .context("Timer receiver gone.")
+    }
+
+    pub fn cancel_timer(&self, name: impl Into<String>) -> anyhow::Result<()> {
+        self.timer_sender
+            .send(TimerCommand::Cancel { name: name.into() })
+            .context("Timer receiver gone.")
+    }
+
+    /// Queues `message` to be written. A `droppable` one is dropped instead if
+    /// the outgoing queue is at its limit, see `QueueConfig::outgoing_soft_limit`.
+    fn write_message<TPayload: Debug + Serialize + PayloadType>(
         &self,
         message: &Message<TPayload>,
+        droppable: bool,
     ) -> anyhow::Result<()> {
-        eprintln!("\tSending message: {message:?}.");
-        self.msg_sender
-            .send(serde_json::to_string(&message).context("Failed to serialize Message")?)
-            .context("Could not send to msg_writer task!")?;
+        debug!(
+            dst = %*message.dst,
+            msg_id = message.body.msg_id.map(|msg_id| *msg_id),
+            in_reply_to = message.body.in_reply_to.map(|in_reply_to| *in_reply_to),
+            body = ?message.body.payload,
+            "Sending message."
+        );
+        let line = serde_json::to_string(message).context("Failed to serialize Message")?;
+        if !self.msg_sender.send(line, droppable)? {
+            debug!(dst = %*message.dst, "Outgoing queue full, dropping message.");
+            self.metrics.record_dropped("outgoing");
+            return Ok(());
+        }
+        self.metrics
+            .record_sent(&message.dst, message.body.payload.payload_type());
         Ok(())
     }
 
-    pub fn reply_to<TPayload: Debug + Serialize>(
+    pub fn reply_to<TPayload: Debug + Serialize + PayloadType>(
         &self,
         received_message: &Message<TPayload>,
         payload: TPayload,
+    ) -> anyhow::Result<MessageID> {
+        self.reply(&received_message.src, received_message.body.msg_id, payload)
+    }
+
+    /// Replies to `received_message` with a Maelstrom `error` body instead of a
+    /// payload of the same type.
+    pub fn reply_with_error<TPayload>(
+        &self,
+        received_message: &Message<TPayload>,
+        error: ErrorPayload,
+    ) -> anyhow::Result<MessageID> {
+        self.reply(&received_message.src, received_message.body.msg_id, error)
+    }
+
+    fn reply<TPayload: Debug + Serialize + PayloadType>(
+        &self,
+        node_id: &NodeID,
+        in_reply_to: Option<MessageID>,
+        payload: TPayload,
     ) -> anyhow::Result<MessageID> {
         let message_id = self.msg_id.fetch_add(1, Ordering::SeqCst).into();
-        self.write_message(&Message {
-            src: self.node_id.clone(),
-            dst: received_message.src.clone(),
-            body: MessageBody {
-                msg_id: Some(message_id),
-                in_reply_to: received_message.body.msg_id,
-                payload,
+        self.write_message(
+            &Message {
+                src: self.node_id.clone(),
+                dst: node_id.clone(),
+                body: MessageBody {
+                    msg_id: Some(message_id),
+                    in_reply_to,
+                    payload,
+                },
             },
-        })?;
+            false,
+        )?;
         Ok(message_id)
     }
 
-    pub fn send_to<TPayload: Debug + Serialize>(
+    pub fn send_to<TPayload: Debug + Serialize + PayloadType>(
+        &self,
+        node_id: &NodeID,
+        payload: TPayload,
+    ) -> anyhow::Result<MessageID> {
+        self.send(node_id, payload, false)
+    }
+
+    /// Like `send_to`, but the message is dropped (as if the network lost it)
+    /// when the outgoing queue is at its limit, see
+    /// `QueueConfig::outgoing_soft_limit`.
+    /// For traffic that gets resent anyway, like gossip.
+    pub fn send_droppable<TPayload: Debug + Serialize + PayloadType>(
         &self,
         node_id: &NodeID,
         payload: TPayload,
+    ) -> anyhow::Result<MessageID> {
+        self.send(node_id, payload, true)
+    }
+
+    fn send<TPayload: Debug + Serialize + PayloadType>(
+        &self,
+        node_id: &NodeID,
+        payload: TPayload,
+        droppable: bool,
     ) -> anyhow::Result<MessageID> {
         let message_id = self.msg_id.fetch_add(1, Ordering::SeqCst).into();
-        self.write_message(&Message {
-            src: self.node_id.clone(),
-            dst: node_id.clone(),
-            body: MessageBody {
-                msg_id: Some(message_id),
-                in_reply_to: None,
-                payload,
+        self.write_message(
+            &Message {
+                src: self.node_id.clone(),
+                dst: node_id.clone(),
+                body: MessageBody {
+                    msg_id: Some(message_id),
+                    in_reply_to: None,
+                    payload,
+                },
             },
-        })?;
+            droppable,
+        )?;
         Ok(message_id)
     }
 
     pub async fn send_and_receive<
-        TPayload: Debug + Serialize,
+        TPayload: Debug + Serialize + PayloadType,
+        TPayloadResponse: DeserializeOwned,
+    >(
+        &self,
+        node_id: &NodeID,
+        payload: TPayload,
+    ) -> anyhow::Result<Message<TPayloadResponse>> {
+        self.send_and_receive_raw(node_id, payload).await?.decode()
+    }
+
+    /// Like `send_and_receive`, but gives up once `deadline` has passed without a
+    /// response, returning an `RpcTimeoutError`. A response arriving after the
+    /// deadline is dropped.
+    pub async fn send_and_receive_with_timeout<
+        TPayload: Debug + Serialize + PayloadType,
+        TPayloadResponse: DeserializeOwned,
+    >(
+        &self,
+        node_id: &NodeID,
+        payload: TPayload,
+        deadline: Duration,
+    ) -> anyhow::Result<Message<TPayloadResponse>> {
+        self.send_and_receive_raw_with_timeout(node_id, payload, deadline)
+            .await?
+            .decode()
+    }
+
+    /// Sends `payload` to `node_id`, resending it according to `policy` when an
+    /// attempt times out or gets a retryable error response. Returns the last
+    /// response (which may be a non-retryable error payload), or the
+    /// `RpcTimeoutError` of the last attempt.
+    pub async fn send_and_receive_with_retry<
+        TPayload: Debug + Serialize + PayloadType + Clone,
         TPayloadResponse: DeserializeOwned,
     >(
         &self,
         node_id: &NodeID,
         payload: TPayload,
+        policy: &RetryPolicy,
     ) -> anyhow::Result<Message<TPayloadResponse>> {
+        self.send_and_receive_raw_with_retry(node_id, payload, policy)
+            .await?
+            .decode()
+    }
+
+    /// Sends `request` to `node_id` and waits for its response, decoded as
+    /// the request's `Rpc::Response`. An `error` response is returned as
+    /// `RpcError::Error`.
+    pub async fn call<TRpc: Rpc>(
+        &self,
+        node_id: &NodeID,
+        request: TRpc,
+    ) -> Result<TRpc::Response, RpcError> {
+        let response = self.send_and_receive_raw(node_id, request).await;
+        decode_rpc_response::<TRpc>(response)
+    }
+
+    /// Like `call`, with the timeout of `send_and_receive_with_timeout`.
+    pub async fn call_with_timeout<TRpc: Rpc>(
+        &self,
+        node_id: &NodeID,
+        request: TRpc,
+        deadline: Duration,
+    ) -> Result<TRpc::Response, RpcError> {
+        let response = self
+            .send_and_receive_raw_with_timeout(node_id, request, deadline)
+            .await;
+        decode_rpc_response::<TRpc>(response)
+    }
+
+    /// Like `call`, with the retries of `send_and_receive_with_retry`.
+    pub async fn call_with_retry<TRpc: Rpc + Clone>(
+        &self,
+        node_id: &NodeID,
+        request: TRpc,
+        policy: &RetryPolicy,
+    ) -> Result<TRpc::Response, RpcError> {
+        let response = self
+            .send_and_receive_raw_with_retry(node_id, request, policy)
+            .await;
+        decode_rpc_response::<TRpc>(response)
+    }
+
+    async fn send_and_receive_raw<TPayload: Debug + Serialize + PayloadType>(
+        &self,
+        node_id: &NodeID,
+        payload: TPayload,
+    ) -> anyhow::Result<RawMessage> {
+        let sent_at = Instant::now();
+        let (_message_id, receiver) = self.send_rpc(node_id, payload)?;
+        let message = receiver.await.context("RPC sender dropped?")?;
+        self.metrics.record_rpc_latency(sent_at.elapsed());
+        Ok(message)
+    }
+
+    pub(crate) async fn send_and_receive_raw_with_timeout<
+        TPayload: Debug + Serialize + PayloadType,
+    >(
+        &self,
+        node_id: &NodeID,
+        payload: TPayload,
+        deadline: Duration,
+    ) -> anyhow::Result<RawMessage> {
+        let sent_at = Instant::now();
+        let (message_id, receiver) = self.send_rpc(node_id, payload)?;
+        match timeout(deadline, receiver).await {
+            Ok(message) => {
+                let message = message.context("RPC sender dropped?")?;
+                self.metrics.record_rpc_latency(sent_at.elapsed());
+                Ok(message)
+            }
+            Err(_elapsed) => {
+                self.response_callback_sender
+                    .send(ResponseCallbackCommand::Cancel(message_id))
+                    .context("RPC callback receiver gone.")?;
+                Err(RpcTimeoutError {
+                    node_id: node_id.clone(),
+                    message_id,
+                    timeout: deadline,
+                }
+                .into())
+            }
+        }
+    }
+
+    async fn send_and_receive_raw_with_retry<TPayload: Debug + Serialize + PayloadType + Clone>(
+        &self,
+        node_id: &NodeID,
+        payload: TPayload,
+        policy: &RetryPolicy,
+    ) -> anyhow::Result<RawMessage> {
+        let mut attempt = 1;
+        loop {
+            let result = self
+                .send_and_receive_raw_with_timeout(node_id, payload.clone(), policy.attempt_timeout)
+                .await;
+            let should_retry = match &result {
+                Ok(message) => policy.is_retryable_response(message),
+                Err(error) => error.is::<RpcTimeoutError>(),
+            };
+            if !should_retry || attempt >= policy.max_attempts {
+                return result;
+            }
+
+            warn!(dst = %**node_id, attempt, "Retrying RPC.");
+            sleep(self.backoff(policy, attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    fn send_rpc<TPayload: Debug + Serialize + PayloadType>(
+        &self,
+        node_id: &NodeID,
+        payload: TPayload,
+    ) -> anyhow::Result<(MessageID, oneshot::Receiver<RawMessage>)> {
         let message_id = self.msg_id.fetch_add(1, Ordering::SeqCst).into();
         let (sender, receiver) = oneshot::channel();
         self.response_callback_sender
-            .send((message_id, sender))
+            .send(ResponseCallbackCommand::Register(message_id, sender))
             .context("RPC callback receiver gone.")?;
-        self.write_message(&Message {
-            src: self.node_id.clone(),
-            dst: node_id.clone(),
-            body: MessageBody {
-                msg_id: Some(message_id),
-                in_reply_to: None,
-                payload,
+        // Never dropped: nothing would tell the caller, who'd wait for a
+        // response until its timeout (or forever).
+        self.write_message(
+            &Message {
+                src: self.node_id.clone(),
+                dst: node_id.clone(),
+                body: MessageBody {
+                    msg_id: Some(message_id),
+                    in_reply_to: None,
+                    payload,
+                },
             },
-        })?;
-        let message = receiver.await.context("RPC sender dropped?")?;
-        message.into_payload()
+            false,
+        )?;
+        Ok((message_id, receiver))
     }
 }
 
@@ -112,24 +511,297 @@ pub trait App {
         writer: &MessageWriter,
     ) -> anyhow::Result<()>;
     async fn tick(&mut self, writer: &MessageWriter) -> anyhow::Result<()>;
+
+    /// Called when a timer scheduled with `MessageWriter::schedule_timer` fires.
+    async fn timer(&mut self, name: String, _writer: &MessageWriter) -> anyhow::Result<()> {
+        warn!(name, "Ignoring timer, App::timer is not implemented.");
+        Ok(())
+    }
+
+    /// How often `tick` is called. Ticks are not delayed by incoming messages,
+    /// but a tick that is still running when the next one is due delays it.
+    ///
+    /// Intervals under 1ms (e.g. `Duration::ZERO` for "as often as possible")
+    /// are raised to 1ms, so that ticks leave time to handle messages.
+    fn tick_interval(&self) -> Duration {
+        Duration::from_millis(10)
+    }
+
+    /// When true, a message that can't be deserialized into `Payload` stops
+    /// the node. By default requests of unknown types are answered with
+    /// `not-supported`, requests of known types that don't deserialize (e.g.
+    /// missing a field) with `malformed-request`, and anything else is ignored.
+    fn strict_payloads(&self) -> bool {
+        false
+    }
+
+    /// Called once the node stops getting messages (its input closed or it got
+    /// `SIGTERM`), after the messages it already got were handled. Use it to
+    /// flush state, e.g. sends the app was batching. RPC responses are still
+    /// delivered while the input is open, and it runs under the same deadline
+    /// as the rest of the shutdown.
+    async fn shutdown(&mut self, _writer: &MessageWriter) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Whether `handle` only hands messages off to be handled elsewhere (like
+    /// `Concurrent` does), which then records `MetricsSnapshot::handler_duration`
+    /// instead of the runtime.
+    #[doc(hidden)]
+    fn handles_in_background(&self) -> bool {
+        false
+    }
+
+    /// Called with the node's `QueueConfig` right after `new`, for apps (like
+    /// `Concurrent`) that size their own limits from it.
+    #[doc(hidden)]
+    fn apply_queue_config(&mut self, _queues: &QueueConfig) {}
+
+    /// Limits how many messages `handle` hands off at once (see
+    /// `handles_in_background`): the next message is only taken off the app's
+    /// queue once one of these is free, while ticks and timers keep running.
+    #[doc(hidden)]
+    fn handler_permits(&self) -> Option<Arc<Semaphore>> {
+        None
+    }
+}
+
+/// Converts the message into the app's payload and lets the app handle it.
+/// Requests that don't convert are answered with `not-supported` or
+/// `malformed-request` (see `App::strict_payloads`), unless the app asked for
+/// strict payloads.
+async fn receive_message<TApp>(
+    app: &mut TApp,
+    message: RawMessage,
+    writer: &MessageWriter,
+) -> anyhow::Result<()>
+where
+    TApp: App,
+    TApp::Payload: DeserializeOwned + MaelstromPayload,
+{
+    let is_request = message.msg_id.is_some() && message.in_reply_to.is_none();
+    let decoded = match message.decode::<TApp::Payload>() {
+        Ok(message) => message,
+        Err(error) if !app.strict_payloads() => {
+            let code = match message.payload_type() {
+                Some(payload_type) if TApp::Payload::is_request_type(payload_type) => {
+                    MaelstromErrorCode::MalformedRequest
+                }
+                _ => MaelstromErrorCode::NotSupported,
+            };
+            warn!(?code, "Ignoring message: {error:#}.");
+            if is_request {
+                writer.reply(
+                    &message.src,
+                    message.msg_id,
+                    ErrorPayload::new(code, format!("{error:#}")),
+                )?;
+            }
+            return Ok(());
+        }
+        Err(error) => return Err(error),
+    };
+    handle_message(app, decoded, writer).await
+}
+
+async fn handle_message<TApp: App>(
+    app: &mut TApp,
+    message: Message<TApp::Payload>,
+    writer: &MessageWriter,
+) -> anyhow::Result<()> {
+    let started_at = Instant::now();
+    let result =
+        handle_catching_failures(message, writer, |message| app.handle(message, writer)).await;
+    if !app.handles_in_background() {
+        writer
+            .metrics()
+            .record_handler_duration(started_at.elapsed());
+    }
+    result
+}
+
+/// Lets `handle` handle the message. If it fails (returns an error or panics)
+/// while handling a request (from a client or another node), the sender gets
+/// an `error` reply instead of the node going down: the app's own
+/// `ErrorPayload` if it returned one, otherwise `crash`.
+pub(crate) async fn handle_catching_failures<TPayload, TFuture>(
+    message: Message<TPayload>,
+    writer: &MessageWriter,
+    handle: impl FnOnce(Message<TPayload>) -> TFuture,
+) -> anyhow::Result<()>
+where
+    TFuture: Future<Output = anyhow::Result<()>>,
+{
+    let src = message.src.clone();
+    let msg_id = message.body.msg_id;
+    let is_request = msg_id.is_some() && message.body.in_reply_to.is_none();
+
+    let result = AssertUnwindSafe(handle(message)).catch_unwind().await;
+    let error = match result {
+        Ok(Ok(())) => return Ok(()),
+        Ok(Err(error)) => error.downcast::<ErrorPayload>().unwrap_or_else(|error| {
+            ErrorPayload::new(MaelstromErrorCode::Crash, format!("{error:#}"))
+        }),
+        Err(panic) => {
+            let text = panic
+                .downcast_ref::<&str>()
+                .map(|text| text.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "App panicked".to_string());
+            ErrorPayload::new(MaelstromErrorCode::Crash, text)
+        }
+    };
+
+    error!("App failed to handle message: {error}.");
+    if is_request {
+        writer.reply(&src, msg_id, error)?;
+    }
+    Ok(())
 }
 
 pub async fn event_loop<
     TApp: App<Payload = TPayload> + Send + 'static,
-    TPayload: 'static + Send + Serialize + DeserializeOwned + Debug,
+    TPayload: 'static + Send + Serialize + DeserializeOwned + MaelstromPayload + Debug,
 >() -> anyhow::Result<()> {
-    let (message_sender, mut message_receiver) = mpsc::unbounded_channel();
+    event_loop_with_queues::<TApp, TPayload>(QueueConfig::default()).await
+}
+
+/// Like `event_loop`, with the runtime's queues sized and handling overflow
+/// according to `queues`.
+pub async fn event_loop_with_queues<
+    TApp: App<Payload = TPayload> + Send + 'static,
+    TPayload: 'static + Send + Serialize + DeserializeOwned + MaelstromPayload + Debug,
+>(
+    queues: QueueConfig,
+) -> anyhow::Result<()> {
+    crate::init_logging();
+
+    let (message_sender, message_receiver) = mpsc::channel(queues.incoming_capacity);
     std::thread::spawn(move || {
         let stdin = io::stdin().lock();
         for line in stdin.lines() {
             let line = line.expect("can read line");
-            if message_sender.send(line).is_err() {
-                eprintln!("Message thread could not send message (receiver gone?). Exiting.");
+            // Blocks while the queue is full, leaving the rest in the pipe.
+            if message_sender.blocking_send(line).is_err() {
+                error!("Message thread could not send message (receiver gone?). Exiting.");
                 break;
             }
         }
     });
 
+    let metrics = Arc::new(Metrics::default());
+    #[cfg(unix)]
+    {
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(error) = log_metrics_on_user_signal(&metrics).await {
+                warn!("Can't log metrics on SIGUSR1: {error}.");
+            }
+        });
+    }
+
+    let (msg_writer_sender, msg_writer_receiver) = outgoing_queue(queues.outgoing_soft_limit);
+    // Tasks the app spawned may still hold a `MessageWriter` once the node has
+    // shut down, so the writer task is told to stop instead of waiting for
+    // every sender to be dropped.
+    let (stop_writer_sender, stop_writer_receiver) = oneshot::channel::<()>();
+    let writer_metrics = metrics.clone();
+    let writer_task_handle: JoinHandle<anyhow::Result<()>> = tokio::spawn(async move {
+        write_messages(
+            msg_writer_receiver,
+            stop_writer_receiver,
+            &writer_metrics,
+            io::stdout(),
+        )
+        .await
+    });
+
+    metrics.record_queue_capacity("outgoing", msg_writer_sender.soft_limit());
+    let result = run_node::<TApp>(
+        message_receiver,
+        msg_writer_sender,
+        metrics.clone(),
+        queues,
+        StdRng::from_entropy(),
+        shutdown_signal(),
+    )
+    .await;
+    let _ = stop_writer_sender.send(());
+    let writer_result = writer_task_handle.await;
+    // Even if the node failed, the summary may help find out why.
+    metrics.log_summary();
+    writer_result??;
+    result
+}
+
+/// Resolves once the node is asked to stop with `SIGTERM` or `SIGINT`.
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(error) = tokio::signal::ctrl_c().await {
+            warn!("Can't listen for SIGINT: {error}.");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(error) => {
+                warn!("Can't listen for SIGTERM: {error}.");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+}
+
+#[cfg(unix)]
+async fn log_metrics_on_user_signal(metrics: &Metrics) -> io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut user_defined = signal(SignalKind::user_defined1())?;
+    while user_defined.recv().await.is_some() {
+        metrics.log_summary();
+    }
+    Ok(())
+}
+
+/// Runs a single node: waits for the init message on `message_receiver`, then
+/// feeds every following message to the app (or the RPC waiting on it) and
+/// sends everything the node writes to `msg_writer_sender`.
+///
+/// The node shuts down when `message_receiver` closes or `shutdown` resolves:
+/// the app gets no new messages, handles the ones it already has and then
+/// `App::shutdown` is called, all within `SHUTDOWN_TIMEOUT`. Meanwhile,
+/// responses to the app's RPCs are still delivered (if the input is open).
+///
+/// Messages for the app wait in a queue of `queues.app_capacity`,
+/// `queues.app_overflow` decides what happens to those that don't fit. Those
+/// that wait for room are parked, and input keeps being read (so that
+/// responses still reach the RPCs that may be holding up the app) until as
+/// many are parked as fit in `message_receiver`.
+pub(crate) async fn run_node<TApp>(
+    mut message_receiver: mpsc::Receiver<String>,
+    msg_writer_sender: OutgoingSender,
+    metrics: Arc<Metrics>,
+    queues: QueueConfig,
+    rng: StdRng,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<()>
+where
+    TApp: App + Send + 'static,
+    TApp::Payload: 'static + Send + Serialize + DeserializeOwned + MaelstromPayload + Debug,
+{
     let init_message = message_receiver
         .recv()
         .await
@@ -140,94 +812,657 @@ pub async fn event_loop<
         anyhow::bail!("Did not get Init message as first message, got: {init_message:?}!");
     };
 
-    let (msg_writer_sender, mut msg_writer_receiver) = mpsc::unbounded_channel::<String>();
-    let writer_task_handle: JoinHandle<anyhow::Result<()>> = tokio::spawn(async move {
-        while let Some(message) = msg_writer_receiver.recv().await {
-            let mut stdout_lock = io::stdout().lock();
-            stdout_lock
-                .write_all(message.as_bytes())
-                .context("Failed to write message to stdout")?;
-            stdout_lock
-                .write_all(b"\n")
-                .context("Failed to write trailing newline")?;
-            stdout_lock.flush().context("Could not flush to stdout")?;
-        }
-        Ok(())
-    });
-
-    let (response_callback_sender, mut response_callback_receiver) = mpsc::unbounded_channel();
-    let mut writer = MessageWriter {
-        msg_id: Arc::new(AtomicU32::new(0)),
-        msg_sender: msg_writer_sender,
-        node_id: node_id.clone(),
-        response_callback_sender,
-    };
+    // Everything the node logs from here on is tagged with its id.
+    let node_span = info_span!("node", node_id = %**node_id);
+    let (writer, mut response_callback_receiver, mut timer_receiver) =
+        MessageWriter::new(node_id.clone(), msg_writer_sender, metrics.clone(), rng);
     let mut app = TApp::new(node_id.clone(), node_ids.clone());
+    app.apply_queue_config(&queues);
+    let QueueConfig {
+        app_capacity,
+        app_overflow,
+        ..
+    } = queues;
     writer.reply_to(&init_message, InitPayload::InitOk)?;
 
-    let (app_message_sender, mut app_message_receiver) =
-        mpsc::unbounded_channel::<Message<serde_json::Value>>();
-    let app_task_handle: JoinHandle<anyhow::Result<()>> = tokio::spawn(async move {
-        let tick_rate = Duration::from_millis(10);
-        let mut last_tick = Instant::now();
+    metrics.record_queue_capacity("incoming", message_receiver.max_capacity());
+    metrics.record_queue_capacity("app", app_capacity);
+    let (app_message_sender, mut app_message_receiver) = mpsc::channel::<RawMessage>(app_capacity);
+    // For shedding messages that don't fit in the app's queue.
+    let shed_writer = writer.clone();
+    let app_task = async move {
+        let handler_permits = app.handler_permits();
+        let mut tick_interval = interval(app.tick_interval().max(MIN_TICK_INTERVAL));
+        tick_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut timers = Timers::default();
         loop {
-            let message = match timeout(tick_rate, app_message_receiver.recv()).await {
-                Ok(Some(message)) => message,
-                Ok(None) => {
-                    eprintln!("Message thread finished unexpectedly? Closing event loop.");
-                    break;
+            let next_timer = timers.next_deadline();
+            // Biased so that ticks and timers fire on time under a steady stream
+            // of messages (and so that simulations are deterministic).
+            tokio::select! {
+                biased;
+                _ = tick_interval.tick() => {
+                    app.tick(&writer).await.context("App failed to tick")?;
                 }
-                Err(_elapsed) => {
-                    if last_tick.elapsed() >= tick_rate {
-                        app.tick(&mut writer).await.context("App failed to tick")?;
-                        last_tick = Instant::now();
+                Some(command) = timer_receiver.recv() => timers.apply(command),
+                _ = sleep_until(next_timer.unwrap_or_else(Instant::now)),
+                    if next_timer.is_some() =>
+                {
+                    for name in timers.take_due(Instant::now()) {
+                        let span = info_span!("timer", name);
+                        app.timer(name, &writer)
+                            .instrument(span)
+                            .await
+                            .context("App failed to handle timer")?;
                     }
-                    continue;
                 }
+                message = next_message(&handler_permits, &mut app_message_receiver) => {
+                    let Some(message) = message else {
+                        break;
+                    };
+                    writer
+                        .metrics()
+                        .record_queue_depth("app", app_message_receiver.len());
+                    let span = message_span(&message);
+                    receive_message(&mut app, message, &writer)
+                        .instrument(span)
+                        .await?;
+                }
+            }
+        }
+        app.shutdown(&writer)
+            .await
+            .context("App failed to shut down")
+    };
+    let mut app_task_handle: JoinHandle<anyhow::Result<()>> =
+        tokio::spawn(app_task.instrument(node_span.clone()));
+
+    tokio::pin!(shutdown);
+    let mut app_message_sender = Some(app_message_sender);
+    let mut shutdown_deadline = None;
+    let mut response_router = ResponseRouter::default();
+    // Messages waiting for room in the app's queue, oldest first.
+    let mut parked = VecDeque::new();
+    let parked_capacity = message_receiver.max_capacity();
+    loop {
+        let message = tokio::select! {
+            biased;
+            result = &mut app_task_handle => return result?,
+            _ = &mut shutdown, if app_message_sender.is_some() => {
+                node_span.in_scope(|| info!("Asked to shut down."));
+                if !parked.is_empty() {
+                    node_span.in_scope(|| {
+                        debug!(count = parked.len(), "Shutting down, dropping parked messages.")
+                    });
+                    parked.clear();
+                }
+                app_message_sender = None;
+                shutdown_deadline = Some(Instant::now() + SHUTDOWN_TIMEOUT);
+                continue;
+            }
+            _ = sleep_until(shutdown_deadline.unwrap_or_else(Instant::now)),
+                if shutdown_deadline.is_some() =>
+            {
+                break;
+            }
+            permit = reserve(app_message_sender.clone()), if !parked.is_empty() => {
+                let permit = permit.context("Failed to send Message to app task!")?;
+                permit.send(parked.pop_front().expect("not empty"));
+                continue;
+            }
+            message = message_receiver.recv(), if parked.len() < parked_capacity => message,
+        };
+        let Some(message) = message else {
+            node_span.in_scope(|| info!("Input closed, shutting down."));
+            break;
+        };
+        response_router.process_commands(&mut response_callback_receiver);
+
+        let message = RawMessage::parse(message)?;
+        metrics.record_queue_depth("incoming", message_receiver.len());
+        metrics.record_received(&message.src, message.payload_type().unwrap_or_default());
+        node_span.in_scope(|| {
+            debug!(
+                src = %*message.src,
+                msg_id = message.msg_id.map(|msg_id| *msg_id),
+                in_reply_to = message.in_reply_to.map(|in_reply_to| *in_reply_to),
+                message = message.as_str(),
+                "Received message."
+            )
+        });
+        let Some(message) = response_router.route(message) else {
+            continue;
+        };
+
+        let Some(app_message_sender) = &app_message_sender else {
+            node_span.in_scope(|| debug!("Shutting down, dropping message."));
+            continue;
+        };
+        // Messages that are parked go first, so the queue counts as full.
+        let message = if parked.is_empty() {
+            match app_message_sender.try_send(message) {
+                Ok(()) => continue,
+                Err(TrySendError::Full(message)) => message,
+                Err(TrySendError::Closed(_)) => {
+                    anyhow::bail!("Failed to send Message to app task!")
+                }
+            }
+        } else {
+            message
+        };
+        let should_wait = match app_overflow {
+            OverflowPolicy::Block => true,
+            OverflowPolicy::Shed => false,
+            OverflowPolicy::DropInternal => !message.src.is_server(),
+        };
+        if should_wait {
+            parked.push_back(message);
+            continue;
+        }
+        node_span.in_scope(|| debug!(src = %*message.src, "App queue full, dropping message."));
+        metrics.record_dropped("app");
+        if app_overflow == OverflowPolicy::Shed
+            && message.msg_id.is_some()
+            && message.in_reply_to.is_none()
+        {
+            shed_writer.reply(
+                &message.src,
+                message.msg_id,
+                ErrorPayload::new(
+                    MaelstromErrorCode::TemporarilyUnavailable,
+                    "Node is overloaded, try again later.",
+                ),
+            )?;
+        }
+    }
+
+    let shutdown_deadline = shutdown_deadline.unwrap_or_else(|| Instant::now() + SHUTDOWN_TIMEOUT);
+    let finish_app = async {
+        // The input is closed, so there are no responses left to wait for: the
+        // parked messages can go straight into the queue.
+        if let Some(app_message_sender) = app_message_sender {
+            for message in parked {
+                if app_message_sender.send(message).await.is_err() {
+                    // The app task stopped, its result says why.
+                    break;
+                }
+            }
+        }
+        (&mut app_task_handle).await
+    };
+    match timeout_at(shutdown_deadline, finish_app).await {
+        Ok(result) => result?,
+        Err(_elapsed) => {
+            node_span.in_scope(|| warn!("App did not shut down within {SHUTDOWN_TIMEOUT:?}."));
+            app_task_handle.abort();
+            Ok(())
+        }
+    }
+}
+
+/// Waits for a free handler (if the app limits them), then for the next
+/// message. The permit is only waited for so that the message stays queued
+/// meanwhile: the app takes one itself when it hands the message off.
+async fn next_message(
+    handler_permits: &Option<Arc<Semaphore>>,
+    app_message_receiver: &mut mpsc::Receiver<RawMessage>,
+) -> Option<RawMessage> {
+    if let Some(handler_permits) = handler_permits {
+        let _permit = handler_permits.acquire().await.expect("never closed");
+    }
+    app_message_receiver.recv().await
+}
+
+/// Waits for room in the app's queue, forever once the queue is gone.
+async fn reserve(
+    app_message_sender: Option<mpsc::Sender<RawMessage>>,
+) -> Result<mpsc::OwnedPermit<RawMessage>, mpsc::error::SendError<()>> {
+    match app_message_sender {
+        Some(app_message_sender) => app_message_sender.reserve_owned().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// The span a message is handled in, so that everything logged while handling
+/// it can be traced back to the request.
+fn message_span(message: &RawMessage) -> tracing::Span {
+    info_span!(
+        "message",
+        src = %*message.src,
+        msg_id = message.msg_id.map(|msg_id| *msg_id),
+        in_reply_to = message.in_reply_to.map(|in_reply_to| *in_reply_to),
+        r#type = message.payload_type(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payload;
+    use serde_json::json;
+
+    /// A node started with `run_node`, fed and read by the test.
+    pub(crate) struct TestNode {
+        input: Option<mpsc::Sender<String>>,
+        output: OutgoingReceiver,
+        handle: JoinHandle<anyhow::Result<()>>,
+    }
+
+    impl TestNode {
+        /// Starts n0 and waits for it to reply to its init message.
+        pub(crate) async fn start<TApp>(app_capacity: usize, app_overflow: OverflowPolicy) -> Self
+        where
+            TApp: App + Send + 'static,
+            TApp::Payload: 'static + Send + Serialize + DeserializeOwned + MaelstromPayload + Debug,
+        {
+            let (input, message_receiver) = mpsc::channel(16);
+            let (msg_writer_sender, output) = outgoing_queue(16);
+            let handle = tokio::spawn(run_node::<TApp>(
+                message_receiver,
+                msg_writer_sender,
+                Arc::new(Metrics::default()),
+                QueueConfig {
+                    app_capacity,
+                    app_overflow,
+                    ..QueueConfig::default()
+                },
+                StdRng::seed_from_u64(0),
+                std::future::pending(),
+            ));
+            let mut node = Self {
+                input: Some(input),
+                output,
+                handle,
             };
+            let init =
+                json!({"type": "init", "msg_id": 0, "node_id": "n0", "node_ids": ["n0", "n1"]});
+            node.send("c0", init).await;
+            assert_eq!(node.recv().await["body"]["type"], "init_ok");
+            node
+        }
 
-            let message = message.into_payload::<TPayload>()?;
-            app.handle(message, &mut writer)
+        pub(crate) async fn send(&self, src: &str, body: serde_json::Value) {
+            let line = json!({"src": src, "dest": "n0", "body": body}).to_string();
+            self.input
+                .as_ref()
+                .expect("node is running")
+                .send(line)
                 .await
-                .context("App failed to handle message")?;
+                .expect("node is running");
+        }
 
-            if last_tick.elapsed() >= tick_rate {
-                app.tick(&mut writer).await.context("App failed to tick")?;
-                last_tick = Instant::now();
+        /// The next message the node writes.
+        pub(crate) async fn recv(&mut self) -> serde_json::Value {
+            let line = self.output.recv().await.expect("node is running");
+            serde_json::from_str(&line).expect("node writes JSON")
+        }
+
+        /// Closes the node's input and waits for it to shut down.
+        pub(crate) async fn stop(mut self) -> anyhow::Result<()> {
+            self.input = None;
+            self.handle.await?
+        }
+    }
+
+    #[payload]
+    #[derive(Debug, PartialEq, Clone)]
+    enum AskPayload {
+        Ask,
+        AskOk { answer: String },
+        Question,
+        QuestionOk,
+        Ping,
+        PingOk,
+        Unexpected,
+    }
+
+    /// Asks n1 a question for every `Ask`, with a short timeout.
+    struct Asker;
+
+    #[async_trait::async_trait]
+    impl App for Asker {
+        type Payload = AskPayload;
+
+        fn new(_node_id: NodeID, _node_ids: Vec<NodeID>) -> Self {
+            Self
+        }
+
+        async fn handle(
+            &mut self,
+            message: Message<Self::Payload>,
+            writer: &MessageWriter,
+        ) -> anyhow::Result<()> {
+            match message.body.payload {
+                AskPayload::Ask => {
+                    let answer = match writer
+                        .send_and_receive_with_timeout::<_, AskPayload>(
+                            &"n1".into(),
+                            AskPayload::Question,
+                            Duration::from_millis(100),
+                        )
+                        .await
+                    {
+                        Ok(_) => "answered".to_string(),
+                        Err(error) if error.is::<RpcTimeoutError>() => "timed out".to_string(),
+                        Err(error) => return Err(error),
+                    };
+                    writer.reply_to(&message, AskPayload::AskOk { answer })?;
+                }
+                AskPayload::Ping => {
+                    writer.reply_to(&message, AskPayload::PingOk)?;
+                }
+                _ => {
+                    writer.send_to(&"c1".into(), AskPayload::Unexpected)?;
+                }
             }
+            Ok(())
         }
-        Ok(())
-    });
 
-    let mut response_callbacks = HashMap::new();
-    while let Some(message) = message_receiver.recv().await {
-        while let Ok((message_id, response_callback)) = response_callback_receiver.try_recv() {
-            let previous_value = response_callbacks.insert(message_id, response_callback);
-            assert!(
-                previous_value.is_none(),
-                "Received multiple response callbacks for same message id, programmer error?"
+        async fn tick(&mut self, _writer: &MessageWriter) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn late_replies_to_timed_out_rpcs_are_dropped() -> anyhow::Result<()> {
+        let mut node = TestNode::start::<Asker>(16, OverflowPolicy::Block).await;
+        node.send("c1", json!({"type": "ask", "msg_id": 1})).await;
+        let question = node.recv().await;
+        assert_eq!(question["body"]["type"], "question");
+
+        let reply = node.recv().await;
+        assert_eq!(reply["body"]["type"], "ask_ok");
+        assert_eq!(reply["body"]["answer"], "timed out");
+
+        let late_reply = json!({
+            "type": "question_ok",
+            "msg_id": 7,
+            "in_reply_to": question["body"]["msg_id"],
+        });
+        node.send("n1", late_reply).await;
+        node.send("c1", json!({"type": "ping", "msg_id": 2})).await;
+        // Would be `unexpected` if the late reply had reached the app.
+        assert_eq!(node.recv().await["body"]["type"], "ping_ok");
+        node.stop().await
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replies_reach_handlers_while_the_app_queue_is_full() -> anyhow::Result<()> {
+        let mut node = TestNode::start::<Asker>(1, OverflowPolicy::Block).await;
+        node.send("c1", json!({"type": "ask", "msg_id": 1})).await;
+        let question = node.recv().await;
+        // The handler waits on n1: the first ping fills the queue and the
+        // second has to wait for room.
+        for msg_id in [2, 3] {
+            node.send("c1", json!({"type": "ping", "msg_id": msg_id}))
+                .await;
+        }
+        let answer = json!({
+            "type": "question_ok",
+            "msg_id": 7,
+            "in_reply_to": question["body"]["msg_id"],
+        });
+        node.send("n1", answer).await;
+
+        let reply = node.recv().await;
+        assert_eq!(reply["body"]["type"], "ask_ok");
+        assert_eq!(reply["body"]["answer"], "answered");
+        for msg_id in [2, 3] {
+            assert_eq!(node.recv().await["body"]["in_reply_to"], msg_id);
+        }
+        node.stop().await
+    }
+
+    #[test]
+    fn only_droppable_messages_are_dropped_when_the_output_is_full() -> anyhow::Result<()> {
+        let (msg_writer_sender, mut output) = outgoing_queue(1);
+        let metrics = Arc::new(Metrics::default());
+        let (writer, _response_callbacks, _timers) = MessageWriter::new(
+            "n0".into(),
+            msg_writer_sender,
+            metrics.clone(),
+            StdRng::seed_from_u64(0),
+        );
+        writer.send_droppable(&"n1".into(), AskPayload::Ping)?;
+        writer.send_droppable(&"n1".into(), AskPayload::Ping)?;
+        writer.send_to(&"n1".into(), AskPayload::Ping)?;
+        writer.send_rpc(&"n1".into(), AskPayload::Question)?;
+        writer.reply(&"n1".into(), Some(1.into()), AskPayload::PingOk)?;
+
+        let mut types = Vec::new();
+        while let Some(line) = output.try_recv() {
+            let message: serde_json::Value = serde_json::from_str(&line)?;
+            types.push(
+                message["body"]["type"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
             );
         }
+        assert_eq!(types, ["ping", "ping", "question", "ping_ok"]);
+        assert_eq!(metrics.snapshot().queues["outgoing"].dropped, 1);
+        Ok(())
+    }
+
+    #[payload]
+    #[derive(Debug, PartialEq, Clone)]
+    enum EchoPayload {
+        Echo { echo: String },
+        EchoOk { echo: String },
+    }
+
+    struct Echo;
+
+    #[async_trait::async_trait]
+    impl App for Echo {
+        type Payload = EchoPayload;
+
+        fn new(_node_id: NodeID, _node_ids: Vec<NodeID>) -> Self {
+            Self
+        }
+
+        async fn handle(
+            &mut self,
+            message: Message<Self::Payload>,
+            writer: &MessageWriter,
+        ) -> anyhow::Result<()> {
+            if let EchoPayload::Echo { echo } = &message.body.payload {
+                writer.reply_to(&message, EchoPayload::EchoOk { echo: echo.clone() })?;
+            }
+            Ok(())
+        }
 
-        let message = serde_json::from_str::<Message<serde_json::Value>>(&message)
-            .context("Couldn't deserialize Message")?;
-        eprintln!("Received message: {message:?}.");
-        if let Some(in_reply_to) = message.body.in_reply_to {
-            if let Some(response_callback) = response_callbacks.remove(&in_reply_to) {
-                if response_callback.send(message).is_err() {
-                    anyhow::bail!("Response callback send failed!");
+        async fn tick(&mut self, _writer: &MessageWriter) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_that_dont_decode_are_answered_by_the_runtime() -> anyhow::Result<()> {
+        let mut node = TestNode::start::<Echo>(16, OverflowPolicy::Block).await;
+        node.send("c1", json!({"type": "frobnicate", "msg_id": 1}))
+            .await;
+        assert_eq!(node.recv().await["body"]["code"], 10);
+        node.send("c1", json!({"type": "echo", "msg_id": 2})).await;
+        assert_eq!(node.recv().await["body"]["code"], 12);
+
+        // Not a request, so nobody is waiting on an answer.
+        let response = json!({"type": "frobnicate_ok", "msg_id": 3, "in_reply_to": 9});
+        node.send("n1", response).await;
+        node.send("c1", json!({"type": "echo", "msg_id": 4, "echo": "hi"}))
+            .await;
+        let reply = node.recv().await;
+        assert_eq!(reply["body"]["type"], "echo_ok");
+        assert_eq!(reply["body"]["echo"], "hi");
+        node.stop().await
+    }
+
+    /// Ticks as often as it can, and answers `Ask` with the number of ticks.
+    struct Eager {
+        ticks: usize,
+    }
+
+    #[async_trait::async_trait]
+    impl App for Eager {
+        type Payload = AskPayload;
+
+        fn new(_node_id: NodeID, _node_ids: Vec<NodeID>) -> Self {
+            Self { ticks: 0 }
+        }
+
+        async fn handle(
+            &mut self,
+            message: Message<Self::Payload>,
+            writer: &MessageWriter,
+        ) -> anyhow::Result<()> {
+            let answer = self.ticks.to_string();
+            writer.reply_to(&message, AskPayload::AskOk { answer })?;
+            Ok(())
+        }
+
+        async fn tick(&mut self, _writer: &MessageWriter) -> anyhow::Result<()> {
+            self.ticks += 1;
+            Ok(())
+        }
+
+        fn tick_interval(&self) -> Duration {
+            Duration::ZERO
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn zero_tick_intervals_tick_every_millisecond() -> anyhow::Result<()> {
+        let mut node = TestNode::start::<Eager>(16, OverflowPolicy::Block).await;
+        sleep(Duration::from_millis(10)).await;
+        node.send("c1", json!({"type": "ask", "msg_id": 1})).await;
+        let ticks = node.recv().await["body"]["answer"]
+            .as_str()
+            .expect("answer is a string")
+            .parse::<usize>()?;
+        assert!((10..=12).contains(&ticks), "ticked {ticks} times");
+        node.stop().await
+    }
+
+    #[test]
+    fn cancelled_callbacks_are_forgotten() {
+        let (command_sender, mut command_receiver) = mpsc::unbounded_channel();
+        let (callback, _response) = oneshot::channel();
+        command_sender
+            .send(ResponseCallbackCommand::Register(1.into(), callback))
+            .unwrap();
+        command_sender
+            .send(ResponseCallbackCommand::Cancel(1.into()))
+            .unwrap();
+
+        let mut router = ResponseRouter::default();
+        router.process_commands(&mut command_receiver);
+        assert!(router.response_callbacks.is_empty());
+
+        let response = |in_reply_to: u32| {
+            let body = json!({"type": "echo_ok", "in_reply_to": in_reply_to});
+            RawMessage::parse(json!({"src": "n1", "dest": "n0", "body": body}).to_string()).unwrap()
+        };
+        assert!(router.route(response(1)).is_none());
+        assert!(router.expired_response_callback_ids.is_empty());
+        assert!(router.route(response(2)).is_some());
+    }
+
+    #[payload]
+    #[derive(Debug)]
+    enum FailPayload {
+        Fail,
+        Reject,
+        Panic,
+        Ping,
+        PingOk,
+    }
+
+    /// Fails every request but `Ping`, in a different way for each.
+    struct Failing;
+
+    #[async_trait::async_trait]
+    impl App for Failing {
+        type Payload = FailPayload;
+
+        fn new(_node_id: NodeID, _node_ids: Vec<NodeID>) -> Self {
+            Self
+        }
+
+        async fn handle(
+            &mut self,
+            message: Message<Self::Payload>,
+            writer: &MessageWriter,
+        ) -> anyhow::Result<()> {
+            match message.body.payload {
+                FailPayload::Fail => anyhow::bail!("Something went wrong"),
+                FailPayload::Reject => Err(ErrorPayload::new(
+                    MaelstromErrorCode::PreconditionFailed,
+                    "Not like that",
+                )
+                .into()),
+                FailPayload::Panic => panic!("Something went very wrong"),
+                _ => {
+                    writer.reply_to(&message, FailPayload::PingOk)?;
+                    Ok(())
                 }
-                continue;
             }
         }
 
-        app_message_sender
-            .send(message)
-            .context("Failed to send Message to app task!")?;
+        async fn tick(&mut self, _writer: &MessageWriter) -> anyhow::Result<()> {
+            Ok(())
+        }
     }
 
-    app_task_handle.await??;
-    writer_task_handle.await??;
+    #[tokio::test]
+    async fn failed_requests_get_error_replies() -> anyhow::Result<()> {
+        let (msg_sender, mut output) = outgoing_queue(16);
+        let (writer, _response_callbacks, _timers) = MessageWriter::new(
+            "n0".into(),
+            msg_sender,
+            Arc::new(Metrics::default()),
+            StdRng::seed_from_u64(0),
+        );
+        let mut app = Failing;
+        let message = |src: &str, body: serde_json::Value| {
+            serde_json::from_value(json!({"src": src, "dest": "n0", "body": body}))
+        };
 
-    Ok(())
+        let expected = [
+            ("fail", 13, "Something went wrong"),
+            ("reject", 22, "Not like that"),
+            ("panic", 13, "Something went very wrong"),
+        ];
+        for (msg_id, (request_type, code, text)) in expected.into_iter().enumerate() {
+            let request = message("c1", json!({"type": request_type, "msg_id": msg_id}))?;
+            handle_message(&mut app, request, &writer).await?;
+            let reply: serde_json::Value =
+                serde_json::from_str(&output.try_recv().context("No reply")?)?;
+            assert_eq!(reply["dest"], "c1");
+            assert_eq!(reply["body"]["type"], "error");
+            assert_eq!(reply["body"]["in_reply_to"], msg_id);
+            assert_eq!(reply["body"]["code"], code);
+            assert_eq!(reply["body"]["text"], text);
+        }
+
+        // Other nodes' requests are answered too.
+        let request = message("n1", json!({"type": "fail", "msg_id": 3}))?;
+        handle_message(&mut app, request, &writer).await?;
+        let reply: serde_json::Value =
+            serde_json::from_str(&output.try_recv().context("No reply")?)?;
+        assert_eq!(reply["dest"], "n1");
+        assert_eq!(reply["body"]["type"], "error");
+        assert_eq!(reply["body"]["in_reply_to"], 3);
+
+        // Still serving after the panic, and messages without a msg_id aren't
+        // answered.
+        let request = message("c1", json!({"type": "fail"}))?;
+        handle_message(&mut app, request, &writer).await?;
+        let request = message("c1", json!({"type": "ping", "msg_id": 4}))?;
+        handle_message(&mut app, request, &writer).await?;
+        let reply: serde_json::Value =
+            serde_json::from_str(&output.try_recv().context("No reply")?)?;
+        assert_eq!(reply["body"]["type"], "ping_ok");
+        assert_eq!(reply["body"]["in_reply_to"], 4);
+        Ok(())
+    }
 }
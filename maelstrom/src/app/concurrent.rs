@@ -0,0 +1,359 @@
+use super::{handle_catching_failures, App};
+use crate::{Message, MessageWriter, NodeID, QueueConfig};
+use anyhow::Context;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::Instant;
+use tracing::{error, warn, Instrument};
+
+/// Like `App`, but every message is handled in its own task, so a handler (or
+/// tick) waiting on an RPC doesn't hold up the rest of the node. The app is
+/// shared between those tasks and has to manage its own state, e.g. behind a
+/// `Mutex`. Handlers can also spawn their own background jobs with a clone of
+/// `self`.
+///
+/// Run it with `event_loop::<Concurrent<MyApp>, _>()`. At most
+/// `QueueConfig::concurrent_handlers` messages are handled at once.
+#[async_trait::async_trait]
+pub trait ConcurrentApp: Send + Sync + 'static {
+    type Payload: Send + 'static;
+
+    fn new(node_id: NodeID, node_ids: Vec<NodeID>) -> Self;
+    async fn handle(
+        self: Arc<Self>,
+        message: Message<Self::Payload>,
+        writer: &MessageWriter,
+    ) -> anyhow::Result<()>;
+    /// Ticks don't overlap: a tick that is due while the previous one is still
+    /// running is skipped.
+    async fn tick(self: Arc<Self>, writer: &MessageWriter) -> anyhow::Result<()>;
+
+    /// A failed timer fails the node, like a failed tick does.
+    async fn timer(self: Arc<Self>, name: String, _writer: &MessageWriter) -> anyhow::Result<()> {
+        warn!(
+            name,
+            "Ignoring timer, ConcurrentApp::timer is not implemented."
+        );
+        Ok(())
+    }
+
+    /// See `App::tick_interval`.
+    fn tick_interval(&self) -> Duration {
+        Duration::from_millis(10)
+    }
+
+    fn strict_payloads(&self) -> bool {
+        false
+    }
+
+    /// Called once the handlers, timers and tick that were running when the
+    /// node started shutting down have finished, see `App::shutdown`.
+    async fn shutdown(self: Arc<Self>, _writer: &MessageWriter) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs a `ConcurrentApp` as an `App`.
+pub struct Concurrent<TApp> {
+    app: Arc<TApp>,
+    tick_task: Option<JoinHandle<anyhow::Result<()>>>,
+    /// Running handlers and timers, waited on when shutting down. Handlers
+    /// deal with their own failures, timers fail the node.
+    tasks: JoinSet<anyhow::Result<()>>,
+    /// One per handler that may run, see `QueueConfig::concurrent_handlers`.
+    handler_permits: Arc<Semaphore>,
+}
+
+#[async_trait::async_trait]
+impl<TApp: ConcurrentApp> App for Concurrent<TApp> {
+    type Payload = TApp::Payload;
+
+    fn new(node_id: NodeID, node_ids: Vec<NodeID>) -> Self {
+        Self {
+            app: Arc::new(TApp::new(node_id, node_ids)),
+            tick_task: None,
+            tasks: JoinSet::new(),
+            handler_permits: Arc::new(Semaphore::new(QueueConfig::default().concurrent_handlers)),
+        }
+    }
+
+    fn apply_queue_config(&mut self, queues: &QueueConfig) {
+        self.handler_permits = Arc::new(Semaphore::new(queues.concurrent_handlers));
+    }
+
+    async fn handle(
+        &mut self,
+        message: Message<Self::Payload>,
+        writer: &MessageWriter,
+    ) -> anyhow::Result<()> {
+        // The app's loop only hands over a message once a permit is free (see
+        // `handler_permits`), so this doesn't wait. Meanwhile the next messages
+        // stay in the app's queue, where `QueueConfig::app_overflow` deals
+        // with the ones that don't fit.
+        let permit = match self.handler_permits.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => self
+                .handler_permits
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("never closed"),
+        };
+        let app = self.app.clone();
+        let writer = writer.clone();
+        let task = async move {
+            let _permit = permit;
+            let started_at = Instant::now();
+            let result =
+                handle_catching_failures(message, &writer, |message| app.handle(message, &writer))
+                    .await;
+            writer
+                .metrics()
+                .record_handler_duration(started_at.elapsed());
+            if let Err(error) = result {
+                error!("Failed to reply to failed message: {error:#}.");
+            }
+            Ok(())
+        };
+        self.spawn(task.in_current_span())
+    }
+
+    async fn tick(&mut self, writer: &MessageWriter) -> anyhow::Result<()> {
+        if let Some(tick_task) = &self.tick_task {
+            if !tick_task.is_finished() {
+                return Ok(());
+            }
+            // A failed tick fails the node, like it does for an `App`.
+            self.tick_task.take().expect("exists").await??;
+        }
+        self.join_finished_tasks()?;
+
+        let app = self.app.clone();
+        let writer = writer.clone();
+        self.tick_task = Some(tokio::spawn(
+            async move { app.tick(&writer).await }.in_current_span(),
+        ));
+        Ok(())
+    }
+
+    async fn timer(&mut self, name: String, writer: &MessageWriter) -> anyhow::Result<()> {
+        let app = self.app.clone();
+        let writer = writer.clone();
+        let task = async move {
+            app.timer(name, &writer)
+                .await
+                .context("App failed to handle timer")
+        };
+        self.spawn(task.in_current_span())
+    }
+
+    fn tick_interval(&self) -> Duration {
+        self.app.tick_interval()
+    }
+
+    fn strict_payloads(&self) -> bool {
+        self.app.strict_payloads()
+    }
+
+    fn handles_in_background(&self) -> bool {
+        true
+    }
+
+    fn handler_permits(&self) -> Option<Arc<Semaphore>> {
+        Some(self.handler_permits.clone())
+    }
+
+    async fn shutdown(&mut self, writer: &MessageWriter) -> anyhow::Result<()> {
+        while let Some(result) = self.tasks.join_next().await {
+            result??;
+        }
+        if let Some(tick_task) = self.tick_task.take() {
+            tick_task.await??;
+        }
+        self.app.clone().shutdown(writer).await
+    }
+}
+
+impl<TApp> Concurrent<TApp> {
+    fn spawn(
+        &mut self,
+        task: impl Future<Output = anyhow::Result<()>> + Send + 'static,
+    ) -> anyhow::Result<()> {
+        self.join_finished_tasks()?;
+        self.tasks.spawn(task);
+        Ok(())
+    }
+
+    /// Forgets the tasks that are done, so the set doesn't grow forever, and
+    /// fails if any of them did.
+    fn join_finished_tasks(&mut self) -> anyhow::Result<()> {
+        while let Some(result) = self.tasks.try_join_next() {
+            result??;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{SimConfig, Simulation};
+    use crate::{payload, ErrorPayload, MaelstromErrorCode, OverflowPolicy};
+    use tokio::time::sleep;
+
+    #[payload]
+    #[derive(Debug, PartialEq, Clone)]
+    enum Payload {
+        Slow,
+        SlowOk,
+        Question,
+        QuestionOk,
+        Ping,
+        PingOk,
+        Wait,
+        WaitOk,
+    }
+
+    /// Answers `Slow` once n1 has (slowly) answered a question, and `Wait` once
+    /// it has ticked.
+    struct Asker {
+        ticked: tokio::sync::Notify,
+    }
+
+    #[async_trait::async_trait]
+    impl ConcurrentApp for Asker {
+        type Payload = Payload;
+
+        fn new(_node_id: NodeID, _node_ids: Vec<NodeID>) -> Self {
+            Self {
+                ticked: tokio::sync::Notify::new(),
+            }
+        }
+
+        async fn handle(
+            self: Arc<Self>,
+            message: Message<Self::Payload>,
+            writer: &MessageWriter,
+        ) -> anyhow::Result<()> {
+            match message.body.payload {
+                Payload::Slow => {
+                    writer
+                        .send_and_receive::<_, Payload>(&"n1".into(), Payload::Question)
+                        .await?;
+                    writer.reply_to(&message, Payload::SlowOk)?;
+                }
+                Payload::Question => {
+                    sleep(Duration::from_secs(1)).await;
+                    writer.reply_to(&message, Payload::QuestionOk)?;
+                }
+                Payload::Ping => {
+                    writer.reply_to(&message, Payload::PingOk)?;
+                }
+                Payload::Wait => {
+                    self.ticked.notified().await;
+                    writer.reply_to(&message, Payload::WaitOk)?;
+                }
+                _ => {}
+            }
+            Ok(())
+        }
+
+        async fn tick(self: Arc<Self>, _writer: &MessageWriter) -> anyhow::Result<()> {
+            self.ticked.notify_waiters();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn handlers_waiting_on_rpcs_dont_hold_up_other_messages() -> anyhow::Result<()> {
+        Simulation::<Concurrent<Asker>>::run(SimConfig::default(), |sim| async move {
+            let client = sim.client();
+            let n0 = &sim.node_ids()[0];
+            let ping = async {
+                sleep(Duration::from_millis(100)).await;
+                let sent_at = Instant::now();
+                client.call(n0, Payload::Ping).await?;
+                anyhow::Ok(sent_at.elapsed())
+            };
+            let (slow, ping) = tokio::join!(client.call(n0, Payload::Slow), ping);
+
+            assert_eq!(slow?, Payload::SlowOk);
+            let ping_latency = ping?;
+            assert!(
+                ping_latency < Duration::from_millis(100),
+                "ping took {ping_latency:?}"
+            );
+
+            // Slow and Ping, each timed once and for as long as it really took.
+            let handler_duration = sim.metrics(n0).expect("n0 exists").handler_duration;
+            assert_eq!(handler_duration.count(), 2);
+            assert!(handler_duration.max() >= Duration::from_secs(1));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn messages_waiting_for_a_handler_dont_hold_up_ticks() -> anyhow::Result<()> {
+        let config = SimConfig {
+            queues: QueueConfig {
+                concurrent_handlers: 1,
+                ..QueueConfig::default()
+            },
+            ..SimConfig::default()
+        };
+        Simulation::<Concurrent<Asker>>::run(config, |sim| async move {
+            let client = &sim.client();
+            let n0 = &sim.node_ids()[0];
+            // The second waits for the first's handler, which waits for a tick.
+            let wait =
+                || tokio::time::timeout(Duration::from_secs(1), client.call(n0, Payload::Wait));
+            let (first, second) = tokio::join!(wait(), wait());
+
+            assert_eq!(first??, Payload::WaitOk);
+            assert_eq!(second??, Payload::WaitOk);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn messages_past_the_handler_limit_overflow_the_app_queue() -> anyhow::Result<()> {
+        let config = SimConfig {
+            queues: QueueConfig {
+                app_capacity: 1,
+                app_overflow: OverflowPolicy::Shed,
+                concurrent_handlers: 1,
+                ..QueueConfig::default()
+            },
+            ..SimConfig::default()
+        };
+        Simulation::<Concurrent<Asker>>::run(config, |sim| async move {
+            let client = &sim.client();
+            let n0 = &sim.node_ids()[0];
+            // Spaced out so that the queue is drained between them, unless
+            // all handlers are busy: one is handled, one waits in the queue
+            // for it to finish and the last doesn't fit.
+            let replies = futures::future::join_all((0..3).map(|index| async move {
+                sleep(Duration::from_millis(50) * index).await;
+                client.call(n0, Payload::Question).await
+            }))
+            .await;
+
+            let mut shed = 0;
+            for reply in replies {
+                match reply {
+                    Ok(reply) => assert_eq!(reply, Payload::QuestionOk),
+                    Err(error) => {
+                        let error = error.downcast::<ErrorPayload>()?;
+                        assert_eq!(error.code, MaelstromErrorCode::TemporarilyUnavailable);
+                        shed += 1;
+                    }
+                }
+            }
+            assert_eq!(shed, 1);
+            Ok(())
+        })
+    }
+}
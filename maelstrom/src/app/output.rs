@@ -0,0 +1,198 @@
+use anyhow::Context;
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+
+use crate::Metrics;
+
+/// Caps on a single batch of `write_messages`, so that under a flood of
+/// messages the first one in a batch isn't held back for long.
+const MAX_WRITE_BATCH_BYTES: usize = 64 * 1024;
+const MAX_WRITE_BATCH_DELAY: Duration = Duration::from_millis(1);
+
+/// Creates the queue of messages waiting to be written, see
+/// `QueueConfig::outgoing_soft_limit`.
+pub(crate) fn outgoing_queue(soft_limit: usize) -> (OutgoingSender, OutgoingReceiver) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let depth = Arc::new(AtomicUsize::new(0));
+    (
+        OutgoingSender {
+            sender,
+            depth: depth.clone(),
+            soft_limit,
+        },
+        OutgoingReceiver { receiver, depth },
+    )
+}
+
+/// The queue is unbounded, only messages that may be dropped are held to its
+/// soft limit (`MessageWriter` can't wait for room, it isn't async).
+#[derive(Debug, Clone)]
+pub(crate) struct OutgoingSender {
+    sender: UnboundedSender<String>,
+    depth: Arc<AtomicUsize>,
+    soft_limit: usize,
+}
+
+impl OutgoingSender {
+    /// Queues `message`, unless it's `droppable` and the queue is at its soft
+    /// limit.
+    /// Returns whether it was queued.
+    pub(crate) fn send(&self, message: String, droppable: bool) -> anyhow::Result<bool> {
+        if droppable && self.depth.load(Ordering::Relaxed) >= self.soft_limit {
+            return Ok(false);
+        }
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        self.sender
+            .send(message)
+            .context("Could not send to msg_writer task!")?;
+        Ok(true)
+    }
+
+    pub(crate) fn soft_limit(&self) -> usize {
+        self.soft_limit
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct OutgoingReceiver {
+    receiver: UnboundedReceiver<String>,
+    depth: Arc<AtomicUsize>,
+}
+
+impl OutgoingReceiver {
+    pub(crate) async fn recv(&mut self) -> Option<String> {
+        let message = self.receiver.recv().await;
+        self.taken(message)
+    }
+
+    pub(crate) fn try_recv(&mut self) -> Option<String> {
+        let message = self.receiver.try_recv().ok();
+        self.taken(message)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.receiver.len()
+    }
+
+    fn taken(&self, message: Option<String>) -> Option<String> {
+        if message.is_some() {
+            self.depth.fetch_sub(1, Ordering::Relaxed);
+        }
+        message
+    }
+}
+
+/// Writes the messages sent on `msg_writer_receiver` to `output`, one per line,
+/// until every sender is gone or `stop` resolves (the messages queued by then
+/// are still written).
+///
+/// Everything that's queued is written in one batch with a single flush,
+/// instead of paying a write and a flush per message.
+pub(crate) async fn write_messages(
+    mut msg_writer_receiver: OutgoingReceiver,
+    mut stop: oneshot::Receiver<()>,
+    metrics: &Metrics,
+    mut output: impl Write,
+) -> anyhow::Result<()> {
+    let mut batch = Vec::new();
+    let mut stopping = false;
+    loop {
+        let message = if stopping {
+            msg_writer_receiver.try_recv()
+        } else {
+            tokio::select! {
+                biased;
+                message = msg_writer_receiver.recv() => message,
+                _ = &mut stop => {
+                    stopping = true;
+                    msg_writer_receiver.try_recv()
+                }
+            }
+        };
+        let Some(mut message) = message else {
+            break;
+        };
+        metrics.record_queue_depth("outgoing", msg_writer_receiver.len());
+
+        let batch_started_at = Instant::now();
+        batch.clear();
+        loop {
+            batch.extend_from_slice(message.as_bytes());
+            batch.push(b'\n');
+            if batch.len() >= MAX_WRITE_BATCH_BYTES
+                || batch_started_at.elapsed() >= MAX_WRITE_BATCH_DELAY
+            {
+                break;
+            }
+            let Some(next_message) = msg_writer_receiver.try_recv() else {
+                break;
+            };
+            message = next_message;
+        }
+        output
+            .write_all(&batch)
+            .context("Failed to write messages to stdout")?;
+        output.flush().context("Could not flush to stdout")?;
+    }
+    output.flush().context("Could not flush to stdout")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct RecordingOutput {
+        written: Vec<u8>,
+        writes: usize,
+    }
+
+    impl Write for &mut RecordingOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            self.writes += 1;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn queued_messages_are_written_in_one_batch() -> anyhow::Result<()> {
+        let (sender, receiver) = outgoing_queue(10);
+        let (stop_sender, stop) = oneshot::channel();
+        for message in ["a", "b", "c"] {
+            sender.send(message.to_string(), false)?;
+        }
+        let _ = stop_sender.send(());
+
+        let mut output = RecordingOutput::default();
+        write_messages(receiver, stop, &Metrics::default(), &mut output).await?;
+
+        assert_eq!(output.written, b"a\nb\nc\n");
+        assert_eq!(output.writes, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn only_droppable_messages_are_held_to_the_soft_limit() -> anyhow::Result<()> {
+        let (sender, mut receiver) = outgoing_queue(1);
+        assert!(sender.send("gossip".to_string(), true)?);
+        assert!(!sender.send("gossip".to_string(), true)?);
+        assert!(sender.send("reply".to_string(), false)?);
+        assert_eq!(receiver.len(), 2);
+
+        receiver.try_recv();
+        receiver.try_recv();
+        assert!(sender.send("gossip".to_string(), true)?);
+        Ok(())
+    }
+}
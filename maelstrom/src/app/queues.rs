@@ -0,0 +1,149 @@
+/// Sizes of the queues between the runtime's tasks, and what happens to
+/// messages that don't fit. Pass it to `event_loop_with_queues`.
+///
+/// The fill levels and drop counts of the queues are in `MetricsSnapshot::queues`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueConfig {
+    /// Lines read from stdin, waiting to be parsed and routed. When full, the
+    /// node stops reading stdin until there's room.
+    pub incoming_capacity: usize,
+    /// Messages waiting to be handled by the app.
+    pub app_capacity: usize,
+    /// `Block` by default, so that no message is lost: nodes fall behind
+    /// instead of failing requests under load. `Shed` keeps them responsive.
+    pub app_overflow: OverflowPolicy,
+    /// How many messages a `Concurrent` app handles at once. Once that many
+    /// are running, the rest wait in the app's queue (and `app_overflow`
+    /// applies to those that don't fit).
+    pub concurrent_handlers: usize,
+    /// Messages waiting to be written to stdout past which those sent with
+    /// `MessageWriter::send_droppable` are dropped, as if the network lost
+    /// them. Only a soft limit: everything else is queued regardless (it'd be
+    /// lost for good otherwise), so the queue itself is unbounded.
+    pub outgoing_soft_limit: usize,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            incoming_capacity: 1024,
+            app_capacity: 1024,
+            app_overflow: OverflowPolicy::Block,
+            concurrent_handlers: 1024,
+            outgoing_soft_limit: 4096,
+        }
+    }
+}
+
+/// What happens to a message for the app when its queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait until there's room. Input is still read in the meantime, so that
+    /// responses reach the app's RPCs, until `incoming_capacity` messages are
+    /// waiting. Past that the node stops reading input, and an app that waits
+    /// on RPCs while handling a message can deadlock.
+    Block,
+    /// Reply to requests with `temporarily-unavailable` and drop any other
+    /// message.
+    Shed,
+    /// Drop messages from other nodes (internal traffic like gossip, which
+    /// should get resent anyway) and wait until there's room for the others,
+    /// like `Block`.
+    DropInternal,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::{outgoing_queue, run_node};
+    use crate::{payload, App, Message, MessageWriter, Metrics, NodeID};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use serde_json::json;
+    use std::sync::Arc;
+    use tokio::sync::{mpsc, Notify, Semaphore};
+
+    static HANDLING: Notify = Notify::const_new();
+    static DONE_HANDLING: Semaphore = Semaphore::const_new(0);
+
+    #[payload]
+    #[derive(Debug, PartialEq, Clone)]
+    enum WorkPayload {
+        Work,
+    }
+
+    /// Handles one message at a time, as slowly as the test wants.
+    struct SlowApp;
+
+    #[async_trait::async_trait]
+    impl App for SlowApp {
+        type Payload = WorkPayload;
+
+        fn new(_node_id: NodeID, _node_ids: Vec<NodeID>) -> Self {
+            Self
+        }
+
+        async fn handle(
+            &mut self,
+            _message: Message<Self::Payload>,
+            _writer: &MessageWriter,
+        ) -> anyhow::Result<()> {
+            HANDLING.notify_one();
+            DONE_HANDLING.acquire().await?.forget();
+            Ok(())
+        }
+
+        async fn tick(&mut self, _writer: &MessageWriter) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn line(body: serde_json::Value) -> String {
+        json!({"src": "c1", "dest": "n0", "body": body}).to_string()
+    }
+
+    #[tokio::test]
+    async fn requests_that_dont_fit_in_the_app_queue_are_shed() -> anyhow::Result<()> {
+        let (message_sender, message_receiver) = mpsc::channel(10);
+        let (msg_writer_sender, mut msg_writer_receiver) = outgoing_queue(10);
+        let metrics = Arc::new(Metrics::default());
+        let node = tokio::spawn(run_node::<SlowApp>(
+            message_receiver,
+            msg_writer_sender,
+            metrics.clone(),
+            QueueConfig {
+                app_capacity: 1,
+                app_overflow: OverflowPolicy::Shed,
+                ..QueueConfig::default()
+            },
+            StdRng::seed_from_u64(0),
+            std::future::pending(),
+        ));
+
+        let init = json!({"type": "init", "msg_id": 0, "node_id": "n0", "node_ids": ["n0"]});
+        message_sender.send(line(init)).await?;
+        message_sender
+            .send(line(json!({"type": "work", "msg_id": 1})))
+            .await?;
+        HANDLING.notified().await;
+        // The first request is being handled and the second fills the queue.
+        for msg_id in [2, 3] {
+            message_sender
+                .send(line(json!({"type": "work", "msg_id": msg_id})))
+                .await?;
+        }
+
+        let _init_ok = msg_writer_receiver.recv().await;
+        let shed: serde_json::Value =
+            serde_json::from_str(&msg_writer_receiver.recv().await.expect("a reply"))?;
+        assert_eq!(shed["body"]["type"], "error");
+        assert_eq!(shed["body"]["code"], 11);
+        assert_eq!(shed["body"]["in_reply_to"], 3);
+        let app_queue = metrics.snapshot().queues["app"].clone();
+        assert_eq!((app_queue.capacity, app_queue.dropped), (1, 1));
+
+        DONE_HANDLING.add_permits(2);
+        drop(message_sender);
+        node.await?
+    }
+}
@@ -0,0 +1,161 @@
+use crate::{MaelstromErrorCode, RawMessage};
+use rand::Rng;
+use std::time::Duration;
+
+/// Controls how `MessageWriter::send_and_receive_with_retry` resends an RPC.
+///
+/// An attempt is retried when it gets no response within `attempt_timeout` or
+/// when it gets an `error` response whose code is in `retryable_error_codes`.
+/// Each attempt is sent with a new message ID, so the request should be safe to
+/// apply more than once (a timed out attempt may still have been processed).
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// How long to wait for a response to each attempt.
+    pub attempt_timeout: Duration,
+    /// Backoff before the second attempt, multiplied by `backoff_multiplier`
+    /// for every attempt after that. A multiplier that makes it negative
+    /// means no backoff, and one that makes it NaN means `max_backoff`.
+    pub initial_backoff: Duration,
+    pub backoff_multiplier: f64,
+    pub max_backoff: Duration,
+    /// Fraction of the backoff (0.0 to 1.0) that is randomized, so that nodes
+    /// retrying at the same time spread out. NaN means no jitter.
+    pub jitter: f64,
+    /// Maelstrom error codes that are worth retrying, e.g.
+    /// `TemporarilyUnavailable`.
+    pub retryable_error_codes: Vec<MaelstromErrorCode>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            attempt_timeout: Duration::from_millis(500),
+            initial_backoff: Duration::from_millis(20),
+            backoff_multiplier: 2.0,
+            max_backoff: Duration::from_secs(1),
+            jitter: 0.5,
+            retryable_error_codes: vec![
+                MaelstromErrorCode::Timeout,
+                MaelstromErrorCode::TemporarilyUnavailable,
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retries, the RPC still times out after `attempt_timeout`.
+    pub fn no_retries(attempt_timeout: Duration) -> Self {
+        Self {
+            max_attempts: 1,
+            attempt_timeout,
+            ..Default::default()
+        }
+    }
+
+    /// The backoff before attempt number `attempt + 1`, without jitter.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let backoff = self.initial_backoff.as_secs_f64() * self.backoff_multiplier.powi(exponent);
+        // `min` also turns NaN into the max.
+        let backoff = backoff.min(self.max_backoff.as_secs_f64());
+        Duration::try_from_secs_f64(backoff).unwrap_or(Duration::ZERO)
+    }
+
+    /// Like `backoff`, with the policy's jitter drawn from `rng`.
+    pub(crate) fn backoff_with_jitter(&self, attempt: u32, rng: &mut impl Rng) -> Duration {
+        let backoff = self.backoff(attempt);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 || jitter.is_nan() {
+            return backoff;
+        }
+        backoff.mul_f64(1.0 - jitter * rng.gen::<f64>())
+    }
+
+    pub(crate) fn is_retryable_response(&self, response: &RawMessage) -> bool {
+        response
+            .error()
+            .is_some_and(|error| self.retryable_error_codes.contains(&error.code))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn backoff_grows_exponentially_up_to_max() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(10),
+            backoff_multiplier: 2.0,
+            max_backoff: Duration::from_millis(50),
+            ..Default::default()
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(10));
+        assert_eq!(policy.backoff(2), Duration::from_millis(20));
+        assert_eq!(policy.backoff(3), Duration::from_millis(40));
+        assert_eq!(policy.backoff(4), Duration::from_millis(50));
+        assert_eq!(policy.backoff(100), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn nonsensical_settings_dont_panic() {
+        let policy = RetryPolicy {
+            backoff_multiplier: -2.0,
+            jitter: f64::NAN,
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(policy.backoff_with_jitter(2, &mut rng), Duration::ZERO);
+        assert_eq!(
+            policy.backoff_with_jitter(1, &mut rng),
+            policy.initial_backoff
+        );
+
+        let policy = RetryPolicy {
+            backoff_multiplier: f64::NAN,
+            ..Default::default()
+        };
+        assert_eq!(policy.backoff(2), policy.max_backoff);
+    }
+
+    #[test]
+    fn jitter_comes_from_the_given_rng() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            jitter: 0.5,
+            ..Default::default()
+        };
+        let backoffs = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..10)
+                .map(|_| policy.backoff_with_jitter(1, &mut rng))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(backoffs(7), backoffs(7));
+        assert!(backoffs(7)
+            .iter()
+            .all(|backoff| *backoff > Duration::from_millis(50)
+                && *backoff <= Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn only_configured_error_codes_are_retryable() {
+        let policy = RetryPolicy {
+            retryable_error_codes: vec![MaelstromErrorCode::TemporarilyUnavailable],
+            ..Default::default()
+        };
+        let response = |body: serde_json::Value| {
+            let message = serde_json::json!({"src": "n1", "dest": "n0", "body": body});
+            RawMessage::parse(message.to_string()).unwrap()
+        };
+        let error = |code: u32| response(serde_json::json!({"type": "error", "code": code}));
+        assert!(policy.is_retryable_response(&error(11)));
+        assert!(!policy.is_retryable_response(&error(22)));
+        assert!(!policy.is_retryable_response(&response(serde_json::json!({"type": "read_ok"}))));
+    }
+}
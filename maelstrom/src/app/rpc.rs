@@ -0,0 +1,122 @@
+use crate::{ErrorPayload, MaelstromErrorCode, PayloadType, RawMessage, RpcTimeoutError};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt::{self, Debug};
+
+/// A request payload that knows the payload type of its response, so that
+/// `MessageWriter::call` can return the response already decoded.
+///
+/// Each request is its own type (like `Ts`), with its reply as the
+/// `Response`. `#[maelstrom::payload]` enums don't implement it, send their
+/// requests with `MessageWriter::send_and_receive` instead.
+pub trait Rpc: Debug + Serialize + PayloadType {
+    type Response: DeserializeOwned;
+}
+
+/// Why an RPC made with `MessageWriter::call` failed.
+#[derive(Debug)]
+pub enum RpcError {
+    /// The node answered with a Maelstrom `error` body.
+    Error(ErrorPayload),
+    /// The answer was neither an `error` nor the request's `Response`.
+    Decode(anyhow::Error),
+    /// No answer, e.g. the request timed out (see `RpcTimeoutError`).
+    Transport(anyhow::Error),
+}
+
+impl RpcError {
+    pub fn code(&self) -> Option<MaelstromErrorCode> {
+        match self {
+            RpcError::Error(error) => Some(error.code),
+            _ => None,
+        }
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            RpcError::Transport(error) => error.is::<RpcTimeoutError>(),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Error(error) => write!(f, "{error}"),
+            RpcError::Decode(error) => write!(f, "Unexpected RPC response: {error:#}"),
+            RpcError::Transport(error) => write!(f, "RPC failed: {error:#}"),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RpcError::Error(error) => Some(error),
+            RpcError::Decode(error) | RpcError::Transport(error) => Some(error.as_ref()),
+        }
+    }
+}
+
+/// Turns the result of an RPC into `TRpc`'s response, checking for an `error`
+/// body first.
+pub(crate) fn decode_rpc_response<TRpc: Rpc>(
+    response: anyhow::Result<RawMessage>,
+) -> Result<TRpc::Response, RpcError> {
+    let response = response.map_err(RpcError::Transport)?;
+    if let Some(error) = response.error() {
+        return Err(RpcError::Error(error));
+    }
+    response
+        .decode::<TRpc::Response>()
+        .map(|message| message.body.payload)
+        .map_err(RpcError::Decode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Debug, serde_derive::Serialize)]
+    struct Echo;
+
+    #[derive(Debug, PartialEq, serde_derive::Deserialize)]
+    struct EchoOk {
+        echo: String,
+    }
+
+    impl PayloadType for Echo {
+        fn payload_type(&self) -> &'static str {
+            "echo"
+        }
+    }
+
+    impl Rpc for Echo {
+        type Response = EchoOk;
+    }
+
+    fn response(mut body: serde_json::Value) -> anyhow::Result<RawMessage> {
+        body["in_reply_to"] = json!(1);
+        RawMessage::parse(json!({"src": "n1", "dest": "n0", "body": body}).to_string())
+    }
+
+    #[test]
+    fn errors_are_decoded_separately_from_responses() {
+        let ok = decode_rpc_response::<Echo>(response(json!({"type": "echo_ok", "echo": "hi"})));
+        assert_eq!(ok.unwrap(), EchoOk { echo: "hi".into() });
+
+        let error = decode_rpc_response::<Echo>(response(
+            json!({"type": "error", "code": 11, "text": "busy"}),
+        ))
+        .unwrap_err();
+        assert_eq!(
+            error.code(),
+            Some(MaelstromErrorCode::TemporarilyUnavailable)
+        );
+
+        let unexpected = decode_rpc_response::<Echo>(response(json!({"type": "echo_ok"})));
+        assert!(matches!(unexpected, Err(RpcError::Decode(_))));
+    }
+}
@@ -0,0 +1,288 @@
+use crate::{ErrorPayload, MaelstromErrorCode, PayloadType, Rpc, RpcError, RpcTimeoutError};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt::{self, Debug};
+use std::marker::PhantomData;
+
+/// Why a key-value operation failed.
+#[derive(Debug)]
+pub enum KvError {
+    /// The service answered with an error (other than the ones the operation
+    /// turns into a result, like `KeyDoesNotExist` for a read).
+    Service {
+        code: MaelstromErrorCode,
+        text: String,
+    },
+    /// The service's answer wasn't the expected response, or its value isn't
+    /// of the requested type.
+    Decode(anyhow::Error),
+    /// No answer, e.g. the request timed out (see `RpcTimeoutError`).
+    Transport(anyhow::Error),
+}
+
+impl KvError {
+    pub fn code(&self) -> Option<MaelstromErrorCode> {
+        match self {
+            KvError::Service { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+
+    /// Whether the operation may still have taken effect: it timed out, or the
+    /// service answered with an indefinite error code. A write or cas that
+    /// failed this way has to be checked by reading the key.
+    pub fn is_indefinite(&self) -> bool {
+        match self {
+            KvError::Service { code, .. } => !code.is_definite(),
+            KvError::Decode(_) => false,
+            KvError::Transport(error) => error.is::<RpcTimeoutError>(),
+        }
+    }
+
+    /// Whether trying again later may succeed: the request timed out, the
+    /// service was temporarily unavailable, or `KeyValueStore::update` gave up
+    /// because of contention. Only safe to retry if the operation is idempotent
+    /// or `is_indefinite` is false.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            KvError::Service { code, .. } => matches!(
+                code,
+                MaelstromErrorCode::Timeout
+                    | MaelstromErrorCode::TemporarilyUnavailable
+                    | MaelstromErrorCode::PreconditionFailed
+            ),
+            KvError::Decode(_) => false,
+            KvError::Transport(error) => error.is::<RpcTimeoutError>(),
+        }
+    }
+}
+
+impl fmt::Display for KvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KvError::Service { code, text } => write!(f, "KV service error {code:?}: {text}"),
+            KvError::Decode(error) => write!(f, "Unexpected KV response: {error:#}"),
+            KvError::Transport(error) => write!(f, "KV request failed: {error:#}"),
+        }
+    }
+}
+
+impl std::error::Error for KvError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            KvError::Service { .. } => None,
+            KvError::Decode(error) | KvError::Transport(error) => Some(error.as_ref()),
+        }
+    }
+}
+
+pub(crate) fn to_json(value: impl Serialize) -> Result<serde_json::Value, KvError> {
+    serde_json::to_value(value).map_err(|error| KvError::Decode(error.into()))
+}
+
+/// The requests and responses of Maelstrom's key-value services (seq-kv,
+/// lin-kv and lww-kv), as one payload type. The clients send the `KvRead`,
+/// `KvWrite` and `KvCas` requests instead, which know their response type.
+#[derive(Debug, PartialEq, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum KVPayload<K, V> {
+    Read {
+        key: K,
+    },
+    ReadOk {
+        value: V,
+    },
+    Write {
+        key: K,
+        value: V,
+    },
+    WriteOk,
+    #[serde(rename = "cas")]
+    CompareAndSet {
+        key: K,
+        from: V,
+        to: V,
+        create_if_not_exists: Option<bool>,
+    },
+    #[serde(rename = "cas_ok")]
+    CompareAndSetOk,
+    Error {
+        code: MaelstromErrorCode,
+        text: String,
+    },
+}
+
+/// A `read` request, answered with a `KvReadOk<V>`.
+#[derive(serde_derive::Serialize)]
+#[serde(tag = "type", rename = "read")]
+pub struct KvRead<K, V> {
+    pub key: K,
+    #[serde(skip)]
+    value: PhantomData<fn() -> V>,
+}
+
+impl<K, V> KvRead<K, V> {
+    pub fn new(key: K) -> Self {
+        Self {
+            key,
+            value: PhantomData,
+        }
+    }
+}
+
+// Not derived, those would require `V: Clone + Debug`.
+impl<K: Clone, V> Clone for KvRead<K, V> {
+    fn clone(&self) -> Self {
+        Self::new(self.key.clone())
+    }
+}
+
+impl<K: Debug, V> Debug for KvRead<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KvRead").field("key", &self.key).finish()
+    }
+}
+
+impl<K, V> PayloadType for KvRead<K, V> {
+    fn payload_type(&self) -> &'static str {
+        "read"
+    }
+}
+
+impl<K: Debug + Serialize, V: DeserializeOwned> Rpc for KvRead<K, V> {
+    type Response = KvReadOk<V>;
+}
+
+#[derive(Debug, PartialEq, Clone, serde_derive::Deserialize)]
+pub struct KvReadOk<V> {
+    pub value: V,
+}
+
+/// A `write` request, answered with a `KvWriteOk`.
+#[derive(Debug, Clone, serde_derive::Serialize)]
+#[serde(tag = "type", rename = "write")]
+pub struct KvWrite<K, V> {
+    pub key: K,
+    pub value: V,
+}
+
+impl<K, V> PayloadType for KvWrite<K, V> {
+    fn payload_type(&self) -> &'static str {
+        "write"
+    }
+}
+
+impl<K: Debug + Serialize, V: Debug + Serialize> Rpc for KvWrite<K, V> {
+    type Response = KvWriteOk;
+}
+
+#[derive(Debug, PartialEq, Clone, serde_derive::Deserialize)]
+pub struct KvWriteOk {}
+
+/// A `cas` request, answered with a `KvCasOk`.
+#[derive(Debug, Clone, serde_derive::Serialize)]
+#[serde(tag = "type", rename = "cas")]
+pub struct KvCas<K, V> {
+    pub key: K,
+    pub from: V,
+    pub to: V,
+    pub create_if_not_exists: Option<bool>,
+}
+
+impl<K, V> PayloadType for KvCas<K, V> {
+    fn payload_type(&self) -> &'static str {
+        "cas"
+    }
+}
+
+impl<K: Debug + Serialize, V: Debug + Serialize> Rpc for KvCas<K, V> {
+    type Response = KvCasOk;
+}
+
+#[derive(Debug, PartialEq, Clone, serde_derive::Deserialize)]
+pub struct KvCasOk {}
+
+impl From<RpcError> for KvError {
+    fn from(error: RpcError) -> Self {
+        match error {
+            RpcError::Error(ErrorPayload { code, text }) => KvError::Service { code, text },
+            RpcError::Decode(error) => KvError::Decode(error),
+            RpcError::Transport(error) => KvError::Transport(error),
+        }
+    }
+}
+
+/// The value read, None if the key doesn't exist.
+pub(crate) fn into_read_result<V>(
+    response: Result<KvReadOk<V>, RpcError>,
+) -> Result<Option<V>, KvError> {
+    match response {
+        Ok(KvReadOk { value }) => Ok(Some(value)),
+        Err(error) if error.code() == Some(MaelstromErrorCode::KeyDoesNotExist) => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+pub(crate) fn into_write_result(response: Result<KvWriteOk, RpcError>) -> Result<(), KvError> {
+    response.map(|KvWriteOk {}| ()).map_err(KvError::from)
+}
+
+/// Whether the value was swapped, false if it didn't match `from`.
+pub(crate) fn into_cas_result(response: Result<KvCasOk, RpcError>) -> Result<bool, KvError> {
+    match response {
+        Ok(KvCasOk {}) => Ok(true),
+        Err(error) if error.code() == Some(MaelstromErrorCode::PreconditionFailed) => Ok(false),
+        Err(error) => Err(error.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    fn service_error(code: MaelstromErrorCode) -> RpcError {
+        RpcError::Error(ErrorPayload::new(code, "no"))
+    }
+
+    #[test]
+    fn cas_errors_other_than_precondition_failed_are_returned() {
+        assert!(into_cas_result(Ok(KvCasOk {})).unwrap());
+        let precondition_failed = service_error(MaelstromErrorCode::PreconditionFailed);
+        assert!(!into_cas_result(Err(precondition_failed)).unwrap());
+
+        let timeout = service_error(MaelstromErrorCode::Timeout);
+        let error = into_cas_result(Err(timeout)).unwrap_err();
+        assert_eq!(error.code(), Some(MaelstromErrorCode::Timeout));
+        assert!(error.is_indefinite() && error.is_transient());
+
+        let missing = service_error(MaelstromErrorCode::KeyDoesNotExist);
+        let error = into_cas_result(Err(missing)).unwrap_err();
+        assert!(!error.is_indefinite() && !error.is_transient());
+
+        let unexpected = RpcError::Decode(anyhow!("not a cas_ok"));
+        assert!(matches!(
+            into_cas_result(Err(unexpected)),
+            Err(KvError::Decode(_))
+        ));
+    }
+
+    #[test]
+    fn requests_serialize_like_kv_payloads() {
+        assert_eq!(
+            serde_json::to_value(KvRead::<_, u32>::new("x")).unwrap(),
+            serde_json::to_value(KVPayload::<_, ()>::Read { key: "x" }).unwrap()
+        );
+        let cas = KvCas {
+            key: "x",
+            from: 1,
+            to: 2,
+            create_if_not_exists: Some(true),
+        };
+        assert_eq!(
+            serde_json::to_value(cas).unwrap(),
+            serde_json::json!({"type": "cas", "key": "x", "from": 1, "to": 2, "create_if_not_exists": true})
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use super::{
+    into_cas_result, into_read_result, into_write_result, KeyValueStore, KvCas, KvError, KvRead,
+    KvWrite,
+};
+use crate::{MessageWriter, RetryPolicy};
+use serde::{de::DeserializeOwned, *};
+use std::fmt::Debug;
+use std::time::Duration;
+
+/// Client for Maelstrom's linearizable key-value service.
+///
+/// lin-kv can fail requests with indefinite errors (and requests can time out),
+/// in which case a write or cas may or may not have taken effect. Those are
+/// returned as errors for which `KvError::is_indefinite` is true, and the only
+/// way to find out what happened is to read the key again. Reads have no
+/// effect, so they are retried according to the retry policy, while writes and
+/// cas are sent once.
+pub struct LinKV<'a> {
+    message_writer: &'a MessageWriter,
+    retry_policy: RetryPolicy,
+}
+
+impl<'a> LinKV<'a> {
+    const LIN_KV_NODE_ID: &'static str = "lin-kv";
+
+    pub fn new(message_writer: &'a MessageWriter) -> Self {
+        Self {
+            message_writer,
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+
+    /// Writes and cas only use the policy's `attempt_timeout`, and
+    /// `KeyValueStore::update` uses its `max_attempts` and backoff.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub async fn read<K: Serialize + Debug + Clone, V: Serialize + DeserializeOwned>(
+        &self,
+        key: K,
+    ) -> Result<Option<V>, KvError> {
+        let response = self
+            .message_writer
+            .call_with_retry(
+                &Self::LIN_KV_NODE_ID.into(),
+                KvRead::<K, V>::new(key),
+                &self.retry_policy,
+            )
+            .await;
+        into_read_result(response)
+    }
+
+    pub async fn write<K: Serialize + Debug, V: Serialize + Debug>(
+        &self,
+        key: K,
+        value: V,
+    ) -> Result<(), KvError> {
+        let response = self
+            .message_writer
+            .call_with_timeout(
+                &Self::LIN_KV_NODE_ID.into(),
+                KvWrite { key, value },
+                self.retry_policy.attempt_timeout,
+            )
+            .await;
+        into_write_result(response)
+    }
+
+    /// Returns false if the key's value wasn't `from`. A missing key is
+    /// created with `to`, like `SeqKV::compare_and_swap` does.
+    pub async fn compare_and_swap<K: Serialize + Debug, V: Serialize + Debug>(
+        &self,
+        key: K,
+        from: V,
+        to: V,
+    ) -> Result<bool, KvError> {
+        let response = self
+            .message_writer
+            .call_with_timeout(
+                &Self::LIN_KV_NODE_ID.into(),
+                KvCas {
+                    key,
+                    from,
+                    to,
+                    create_if_not_exists: Some(true),
+                },
+                self.retry_policy.attempt_timeout,
+            )
+            .await;
+        into_cas_result(response)
+    }
+}
+
+#[async_trait::async_trait]
+impl KeyValueStore for LinKV<'_> {
+    async fn read<K, V>(&self, key: K) -> Result<Option<V>, KvError>
+    where
+        K: Serialize + Debug + Clone + Send,
+        V: Serialize + DeserializeOwned + Send,
+    {
+        LinKV::read(self, key).await
+    }
+
+    async fn fresh_read<K, V>(&self, key: K) -> Result<Option<V>, KvError>
+    where
+        K: Serialize + Debug + Clone + Send,
+        V: Serialize + DeserializeOwned + Send,
+    {
+        // Reads are linearizable, so never stale.
+        LinKV::read(self, key).await
+    }
+
+    async fn write<K, V>(&self, key: K, value: V) -> Result<(), KvError>
+    where
+        K: Serialize + Debug + Send,
+        V: Serialize + Debug + Send,
+    {
+        LinKV::write(self, key, value).await
+    }
+
+    async fn compare_and_swap<K, V>(&self, key: K, from: V, to: V) -> Result<bool, KvError>
+    where
+        K: Serialize + Debug + Send,
+        V: Serialize + Debug + Send,
+    {
+        LinKV::compare_and_swap(self, key, from, to).await
+    }
+
+    fn update_retry_policy(&self) -> RetryPolicy {
+        self.retry_policy.clone()
+    }
+
+    fn update_backoff(&self, attempt: u32) -> Duration {
+        self.message_writer.backoff(&self.retry_policy, attempt)
+    }
+}
@@ -0,0 +1,72 @@
+use crate::{MessageWriter, PayloadType, RetryPolicy, Rpc};
+use futures::future::try_join_all;
+
+/// Client for Maelstrom's linearizable timestamp oracle.
+///
+/// Every timestamp is greater than all timestamps the service handed out
+/// before the request was sent, to any node. Requesting a timestamp has no
+/// effect, so requests are retried according to the retry policy (a lost
+/// request only wastes a timestamp).
+pub struct LinTso<'a> {
+    message_writer: &'a MessageWriter,
+    retry_policy: RetryPolicy,
+}
+
+impl<'a> LinTso<'a> {
+    const LIN_TSO_NODE_ID: &'static str = "lin-tso";
+
+    pub fn new(message_writer: &'a MessageWriter) -> Self {
+        Self {
+            message_writer,
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub async fn timestamp(&self) -> anyhow::Result<u64> {
+        let response = self
+            .message_writer
+            .call_with_retry(&Self::LIN_TSO_NODE_ID.into(), Ts {}, &self.retry_policy)
+            .await?;
+        Ok(response.ts)
+    }
+
+    /// Requests `count` timestamps at once instead of one round-trip after
+    /// the other. Returned in ascending order.
+    pub async fn timestamps(&self, count: usize) -> anyhow::Result<Vec<u64>> {
+        let mut timestamps = try_join_all((0..count).map(|_| self.timestamp())).await?;
+        timestamps.sort_unstable();
+        Ok(timestamps)
+    }
+}
+
+/// A `ts` request, answered with a `TsOk`.
+#[derive(Debug, PartialEq, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(tag = "type", rename = "ts")]
+pub struct Ts {}
+
+impl PayloadType for Ts {
+    fn payload_type(&self) -> &'static str {
+        "ts"
+    }
+}
+
+impl Rpc for Ts {
+    type Response = TsOk;
+}
+
+#[derive(Debug, PartialEq, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(tag = "type", rename = "ts_ok")]
+pub struct TsOk {
+    pub ts: u64,
+}
+
+impl PayloadType for TsOk {
+    fn payload_type(&self) -> &'static str {
+        "ts_ok"
+    }
+}
@@ -0,0 +1,152 @@
+use super::{
+    into_cas_result, into_read_result, into_write_result, KeyValueStore, KvCas, KvError, KvRead,
+    KvWrite,
+};
+use crate::{MaelstromErrorCode, MessageWriter, RetryPolicy};
+use serde::{de::DeserializeOwned, *};
+use std::fmt::Debug;
+use std::time::Duration;
+
+/// Client for Maelstrom's last-write-wins key-value service.
+///
+/// lww-kv is totally available: every request succeeds, even while the network
+/// is partitioned, but the service is only eventually consistent. Each replica
+/// keeps the write with the latest timestamp, so:
+/// - a read may not see the caller's own recent writes, or any recent write,
+/// - of two concurrent writes to a key, one is silently lost,
+/// - a cas only compares against the replica it lands on, so two nodes can
+///   both succeed in swapping the same `from` value.
+///
+/// Use it for state that tolerates those anomalies, or as the store behind a
+/// design that brings its own conflict resolution (e.g. CRDT merges).
+///
+/// Requests time out like `LinKV`'s do: reads are retried according to the
+/// retry policy, while a write or cas that times out may or may not have taken
+/// effect, see `KvError::is_indefinite`.
+pub struct LwwKV<'a> {
+    message_writer: &'a MessageWriter,
+    retry_policy: RetryPolicy,
+}
+
+impl<'a> LwwKV<'a> {
+    const LWW_KV_NODE_ID: &'static str = "lww-kv";
+
+    pub fn new(message_writer: &'a MessageWriter) -> Self {
+        Self {
+            message_writer,
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+
+    /// Writes and cas only use the policy's `attempt_timeout`, and
+    /// `KeyValueStore::update` uses its `max_attempts` and backoff.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Returns the value of the key on some replica, which may be stale.
+    pub async fn read<K: Serialize + Debug + Clone, V: Serialize + DeserializeOwned>(
+        &self,
+        key: K,
+    ) -> Result<Option<V>, KvError> {
+        let response = self
+            .message_writer
+            .call_with_retry(
+                &Self::LWW_KV_NODE_ID.into(),
+                KvRead::<K, V>::new(key),
+                &self.retry_policy,
+            )
+            .await;
+        into_read_result(response)
+    }
+
+    /// Overwrites the key, unless a write with a later timestamp wins.
+    pub async fn write<K: Serialize + Debug, V: Serialize + Debug>(
+        &self,
+        key: K,
+        value: V,
+    ) -> Result<(), KvError> {
+        let response = self
+            .message_writer
+            .call_with_timeout(
+                &Self::LWW_KV_NODE_ID.into(),
+                KvWrite { key, value },
+                self.retry_policy.attempt_timeout,
+            )
+            .await;
+        into_write_result(response)
+    }
+
+    /// Returns false if the key's value on the replica wasn't `from`. A missing
+    /// key is created with `to`. Not atomic across replicas, see `LwwKV`.
+    pub async fn compare_and_swap<K: Serialize + Debug, V: Serialize + Debug>(
+        &self,
+        key: K,
+        from: V,
+        to: V,
+    ) -> Result<bool, KvError> {
+        let response = self
+            .message_writer
+            .call_with_timeout(
+                &Self::LWW_KV_NODE_ID.into(),
+                KvCas {
+                    key,
+                    from,
+                    to,
+                    create_if_not_exists: Some(true),
+                },
+                self.retry_policy.attempt_timeout,
+            )
+            .await;
+        into_cas_result(response)
+    }
+}
+
+#[async_trait::async_trait]
+impl KeyValueStore for LwwKV<'_> {
+    async fn read<K, V>(&self, key: K) -> Result<Option<V>, KvError>
+    where
+        K: Serialize + Debug + Clone + Send,
+        V: Serialize + DeserializeOwned + Send,
+    {
+        LwwKV::read(self, key).await
+    }
+
+    async fn fresh_read<K, V>(&self, _key: K) -> Result<Option<V>, KvError>
+    where
+        K: Serialize + Debug + Clone + Send,
+        V: Serialize + DeserializeOwned + Send,
+    {
+        // Every replica may be missing the latest write, so no read is
+        // guaranteed to be current.
+        Err(KvError::Service {
+            code: MaelstromErrorCode::NotSupported,
+            text: "lww-kv can't guarantee a fresh read".to_string(),
+        })
+    }
+
+    async fn write<K, V>(&self, key: K, value: V) -> Result<(), KvError>
+    where
+        K: Serialize + Debug + Send,
+        V: Serialize + Debug + Send,
+    {
+        LwwKV::write(self, key, value).await
+    }
+
+    async fn compare_and_swap<K, V>(&self, key: K, from: V, to: V) -> Result<bool, KvError>
+    where
+        K: Serialize + Debug + Send,
+        V: Serialize + Debug + Send,
+    {
+        LwwKV::compare_and_swap(self, key, from, to).await
+    }
+
+    fn update_retry_policy(&self) -> RetryPolicy {
+        self.retry_policy.clone()
+    }
+
+    fn update_backoff(&self, attempt: u32) -> Duration {
+        self.message_writer.backoff(&self.retry_policy, attempt)
+    }
+}
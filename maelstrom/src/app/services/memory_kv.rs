@@ -0,0 +1,97 @@
+use super::{to_json, KeyValueStore, KvError};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+/// A linearizable in-memory `KeyValueStore`, for testing app logic without a
+/// Maelstrom service. Clones share the same values.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryKV {
+    /// Keyed by the key's JSON, since keys can be any JSON value.
+    values: Arc<Mutex<HashMap<String, serde_json::Value>>>,
+}
+
+impl MemoryKV {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait::async_trait]
+impl KeyValueStore for MemoryKV {
+    async fn read<K, V>(&self, key: K) -> Result<Option<V>, KvError>
+    where
+        K: Serialize + Debug + Clone + Send,
+        V: Serialize + DeserializeOwned + Send,
+    {
+        let key = to_json(key)?.to_string();
+        let value = self.values.lock().expect("not poisoned").get(&key).cloned();
+        value
+            .map(|value| {
+                serde_json::from_value(value).map_err(|error| KvError::Decode(error.into()))
+            })
+            .transpose()
+    }
+
+    async fn fresh_read<K, V>(&self, key: K) -> Result<Option<V>, KvError>
+    where
+        K: Serialize + Debug + Clone + Send,
+        V: Serialize + DeserializeOwned + Send,
+    {
+        // Reads are linearizable, so never stale.
+        KeyValueStore::read(self, key).await
+    }
+
+    async fn write<K, V>(&self, key: K, value: V) -> Result<(), KvError>
+    where
+        K: Serialize + Debug + Send,
+        V: Serialize + Debug + Send,
+    {
+        let (key, value) = (to_json(key)?.to_string(), to_json(value)?);
+        self.values.lock().expect("not poisoned").insert(key, value);
+        Ok(())
+    }
+
+    async fn compare_and_swap<K, V>(&self, key: K, from: V, to: V) -> Result<bool, KvError>
+    where
+        K: Serialize + Debug + Send,
+        V: Serialize + Debug + Send,
+    {
+        let (key, from, to) = (to_json(key)?.to_string(), to_json(from)?, to_json(to)?);
+        let mut values = self.values.lock().expect("not poisoned");
+        match values.get(&key) {
+            Some(current) if *current != from => Ok(false),
+            _ => {
+                values.insert(key, to);
+                Ok(true)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn compare_and_swap_only_swaps_the_expected_value() -> anyhow::Result<()> {
+        let kv = MemoryKV::new();
+        assert_eq!(kv.read::<_, u32>("counter").await?, None);
+        assert!(kv.compare_and_swap("counter", 0, 1).await?);
+        assert!(!kv.compare_and_swap("counter", 0, 2).await?);
+        assert!(kv.clone().compare_and_swap("counter", 1, 2).await?);
+        assert_eq!(kv.read::<_, u32>("counter").await?, Some(2));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_applies_to_the_current_value() -> anyhow::Result<()> {
+        let kv = MemoryKV::new();
+        let increment = |value: Option<&u32>| value.copied().unwrap_or_default() + 1;
+        assert_eq!(kv.update("counter", increment).await?, 1);
+        assert_eq!(kv.update("counter", increment).await?, 2);
+        assert_eq!(kv.read::<_, u32>("counter").await?, Some(2));
+        Ok(())
+    }
+}
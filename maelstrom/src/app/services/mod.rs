@@ -1,3 +1,17 @@
+mod kv;
+mod lin_kv;
+mod lin_tso;
+mod lww_kv;
+mod memory_kv;
 mod seq_kv;
+mod session;
+mod store;
 
+pub use self::kv::*;
+pub use self::lin_kv::*;
+pub use self::lin_tso::*;
+pub use self::lww_kv::*;
+pub use self::memory_kv::*;
 pub use self::seq_kv::*;
+pub use self::session::*;
+pub use self::store::*;
@@ -1,53 +1,85 @@
-use crate::MessageWriter;
+use super::{
+    into_cas_result, into_read_result, into_write_result, KeyValueStore, KvCas, KvError, KvRead,
+    KvWrite,
+};
+use crate::{MessageWriter, RetryPolicy};
 use serde::{de::DeserializeOwned, *};
 use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
 
+/// Makes every marker write of `SeqKV::fresh_read` unique.
+static NEXT_FRESH_READ_MARKER: AtomicU64 = AtomicU64::new(0);
+
+/// Client for Maelstrom's sequentially consistent key-value service.
+///
+/// Requests time out like `LinKV`'s do: reads are retried according to the
+/// retry policy, while a write or cas that times out may or may not have taken
+/// effect, see `KvError::is_indefinite`.
 pub struct SeqKV<'a> {
     message_writer: &'a MessageWriter,
+    retry_policy: RetryPolicy,
 }
 
 impl<'a> SeqKV<'a> {
-    const SEQ_KV_NODE_ID: &str = "seq-kv";
+    const SEQ_KV_NODE_ID: &'static str = "seq-kv";
 
     pub fn new(message_writer: &'a MessageWriter) -> Self {
-        Self { message_writer }
+        Self {
+            message_writer,
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+
+    /// Writes and cas only use the policy's `attempt_timeout`, and
+    /// `KeyValueStore::update` uses its `max_attempts` and backoff.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
     }
 
-    pub async fn read<K: Serialize + Debug, V: Serialize + DeserializeOwned>(
+    pub async fn read<K: Serialize + Debug + Clone, V: Serialize + DeserializeOwned>(
         &self,
         key: K,
-    ) -> anyhow::Result<Option<V>> {
+    ) -> Result<Option<V>, KvError> {
         let response = self
             .message_writer
-            .send_and_receive::<_, KVPayload<(), V>>(
+            .call_with_retry(
                 &Self::SEQ_KV_NODE_ID.into(),
-                KVPayload::<K, ()>::Read { key },
+                KvRead::<K, V>::new(key),
+                &self.retry_policy,
             )
-            .await?;
-        Ok(match response.body.payload {
-            // key-does-not-exist
-            KVPayload::Error { code, text: _ } if code == 20 => None,
-            KVPayload::ReadOk { value } => Some(value),
-            _ => anyhow::bail!("Expected ReadOk in response to Read."),
-        })
+            .await;
+        into_read_result(response)
+    }
+
+    /// Reads the key's current value. seq-kv may serve a node stale values
+    /// until the node writes something, so this first writes to a marker key
+    /// of the node's own (an extra round-trip).
+    pub async fn fresh_read<K: Serialize + Debug + Clone, V: Serialize + DeserializeOwned>(
+        &self,
+        key: K,
+    ) -> Result<Option<V>, KvError> {
+        let marker_key = format!("fresh-read-marker-{}", **self.message_writer.node_id());
+        let marker = NEXT_FRESH_READ_MARKER.fetch_add(1, Ordering::Relaxed);
+        self.write(marker_key, marker).await?;
+        self.read(key).await
     }
 
     pub async fn write<K: Serialize + Debug, V: Serialize + Debug>(
         &self,
         key: K,
         value: V,
-    ) -> anyhow::Result<()> {
+    ) -> Result<(), KvError> {
         let response = self
             .message_writer
-            .send_and_receive::<KVPayload<K, V>, KVPayload<(), ()>>(
+            .call_with_timeout(
                 &Self::SEQ_KV_NODE_ID.into(),
-                KVPayload::Write { key, value },
+                KvWrite { key, value },
+                self.retry_policy.attempt_timeout,
             )
-            .await?;
-        let KVPayload::WriteOk = response.body.payload else {
-            anyhow::bail!("Expected WriteOk in response to Write.");
-        };
-        Ok(())
+            .await;
+        into_write_result(response)
     }
 
     pub async fn compare_and_swap<K: Serialize + Debug, V: Serialize + Debug>(
@@ -55,54 +87,63 @@ impl<'a> SeqKV<'a> {
         key: K,
         from: V,
         to: V,
-    ) -> anyhow::Result<bool> {
+    ) -> Result<bool, KvError> {
         let response = self
             .message_writer
-            .send_and_receive::<KVPayload<K, V>, KVPayload<(), ()>>(
+            .call_with_timeout(
                 &Self::SEQ_KV_NODE_ID.into(),
-                KVPayload::CompareAndSet {
+                KvCas {
                     key,
                     from,
                     to,
                     create_if_not_exists: Some(true),
                 },
+                self.retry_policy.attempt_timeout,
             )
-            .await?;
-        Ok(match response.body.payload {
-            // precondition-failed
-            KVPayload::Error { code, text: _ } if code == 22 => false,
-            KVPayload::CompareAndSetOk => true,
-            _ => anyhow::bail!("Expected CompareAndSetOk in response to CompareAndSet."),
-        })
+            .await;
+        into_cas_result(response)
     }
 }
 
-#[derive(Debug, PartialEq, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
-#[serde(tag = "type")]
-#[serde(rename_all = "snake_case")]
-pub enum KVPayload<K, V> {
-    Read {
-        key: K,
-    },
-    ReadOk {
-        value: V,
-    },
-    Write {
-        key: K,
-        value: V,
-    },
-    WriteOk,
-    #[serde(rename = "cas")]
-    CompareAndSet {
-        key: K,
-        from: V,
-        to: V,
-        create_if_not_exists: Option<bool>,
-    },
-    #[serde(rename = "cas_ok")]
-    CompareAndSetOk,
-    Error {
-        code: u32,
-        text: String,
-    },
+#[async_trait::async_trait]
+impl KeyValueStore for SeqKV<'_> {
+    async fn read<K, V>(&self, key: K) -> Result<Option<V>, KvError>
+    where
+        K: Serialize + Debug + Clone + Send,
+        V: Serialize + DeserializeOwned + Send,
+    {
+        SeqKV::read(self, key).await
+    }
+
+    async fn write<K, V>(&self, key: K, value: V) -> Result<(), KvError>
+    where
+        K: Serialize + Debug + Send,
+        V: Serialize + Debug + Send,
+    {
+        SeqKV::write(self, key, value).await
+    }
+
+    async fn compare_and_swap<K, V>(&self, key: K, from: V, to: V) -> Result<bool, KvError>
+    where
+        K: Serialize + Debug + Send,
+        V: Serialize + Debug + Send,
+    {
+        SeqKV::compare_and_swap(self, key, from, to).await
+    }
+
+    async fn fresh_read<K, V>(&self, key: K) -> Result<Option<V>, KvError>
+    where
+        K: Serialize + Debug + Clone + Send,
+        V: Serialize + DeserializeOwned + Send,
+    {
+        SeqKV::fresh_read(self, key).await
+    }
+
+    fn update_retry_policy(&self) -> RetryPolicy {
+        self.retry_policy.clone()
+    }
+
+    fn update_backoff(&self, attempt: u32) -> Duration {
+        self.message_writer.backoff(&self.retry_policy, attempt)
+    }
 }
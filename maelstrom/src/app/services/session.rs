@@ -0,0 +1,205 @@
+use super::{to_json, KeyValueStore, KvError};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Mutex;
+
+/// Monotonic reads and read-your-writes on top of a `KeyValueStore` whose
+/// reads can be stale, like `SeqKV`. Not for `LwwKV`, which can't serve the
+/// fresh reads the session relies on.
+///
+/// The session remembers the last value it saw of every key (read or written
+/// through it). A read returning anything else may be stale, so it's replaced
+/// by a `fresh_read`: reads never go back to a value older than one the
+/// session already saw, at the cost of an extra round-trip whenever a key
+/// changed. Keep one session per node (e.g. in the app) and pass it the store
+/// to use for each operation.
+#[derive(Debug, Default)]
+pub struct KvSession {
+    /// Keyed by the key's JSON. Null if the key was seen to not exist, None if
+    /// it changed to a value the session hasn't seen (after a failed cas).
+    last_seen: Mutex<HashMap<String, Option<serde_json::Value>>>,
+}
+
+impl KvSession {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn read<K, V>(&self, kv: &impl KeyValueStore, key: K) -> Result<Option<V>, KvError>
+    where
+        K: Serialize + Debug + Clone + Send,
+        V: Serialize + DeserializeOwned + Send,
+    {
+        let session_key = to_json(&key)?.to_string();
+        let value = kv.read::<K, V>(key.clone()).await?;
+        let seen = to_json(&value)?;
+        {
+            let mut last_seen = self.last_seen.lock().expect("not poisoned");
+            match last_seen.get(&session_key) {
+                Some(last_seen) if last_seen.as_ref() != Some(&seen) => {}
+                _ => {
+                    last_seen.insert(session_key, Some(seen));
+                    return Ok(value);
+                }
+            }
+        }
+        self.fresh_read(kv, key).await
+    }
+
+    /// Reads the key's current value, see `KeyValueStore::fresh_read`.
+    pub async fn fresh_read<K, V>(
+        &self,
+        kv: &impl KeyValueStore,
+        key: K,
+    ) -> Result<Option<V>, KvError>
+    where
+        K: Serialize + Debug + Clone + Send,
+        V: Serialize + DeserializeOwned + Send,
+    {
+        let session_key = to_json(&key)?.to_string();
+        let value = kv.fresh_read::<K, V>(key).await?;
+        let seen = to_json(&value)?;
+        self.last_seen
+            .lock()
+            .expect("not poisoned")
+            .insert(session_key, Some(seen));
+        Ok(value)
+    }
+
+    pub async fn write<K, V>(
+        &self,
+        kv: &impl KeyValueStore,
+        key: K,
+        value: V,
+    ) -> Result<(), KvError>
+    where
+        K: Serialize + Debug + Send,
+        V: Serialize + Debug + Send,
+    {
+        let session_key = to_json(&key)?.to_string();
+        let seen = to_json(&value)?;
+        kv.write(key, value).await?;
+        self.last_seen
+            .lock()
+            .expect("not poisoned")
+            .insert(session_key, Some(seen));
+        Ok(())
+    }
+
+    pub async fn compare_and_swap<K, V>(
+        &self,
+        kv: &impl KeyValueStore,
+        key: K,
+        from: V,
+        to: V,
+    ) -> Result<bool, KvError>
+    where
+        K: Serialize + Debug + Send,
+        V: Serialize + Debug + Send,
+    {
+        let session_key = to_json(&key)?.to_string();
+        let seen = to_json(&to)?;
+        let swapped = kv.compare_and_swap(key, from, to).await?;
+        // If it failed, all we know is that the value isn't `from`: it may have
+        // changed since the session last saw it, so the next read has to be
+        // fresh.
+        let seen = swapped.then_some(seen);
+        self.last_seen
+            .lock()
+            .expect("not poisoned")
+            .insert(session_key, seen);
+        Ok(swapped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryKV;
+
+    /// A store whose plain reads return whatever value `stale` holds.
+    struct StaleKV {
+        kv: MemoryKV,
+        stale: Mutex<Option<u32>>,
+    }
+
+    #[async_trait::async_trait]
+    impl KeyValueStore for StaleKV {
+        async fn read<K, V>(&self, _key: K) -> Result<Option<V>, KvError>
+        where
+            K: Serialize + Debug + Clone + Send,
+            V: Serialize + DeserializeOwned + Send,
+        {
+            let stale = *self.stale.lock().unwrap();
+            serde_json::from_value(to_json(stale)?).map_err(|e| KvError::Decode(e.into()))
+        }
+
+        async fn fresh_read<K, V>(&self, key: K) -> Result<Option<V>, KvError>
+        where
+            K: Serialize + Debug + Clone + Send,
+            V: Serialize + DeserializeOwned + Send,
+        {
+            self.kv.read(key).await
+        }
+
+        async fn write<K, V>(&self, key: K, value: V) -> Result<(), KvError>
+        where
+            K: Serialize + Debug + Send,
+            V: Serialize + Debug + Send,
+        {
+            self.kv.write(key, value).await
+        }
+
+        async fn compare_and_swap<K, V>(&self, key: K, from: V, to: V) -> Result<bool, KvError>
+        where
+            K: Serialize + Debug + Send,
+            V: Serialize + Debug + Send,
+        {
+            self.kv.compare_and_swap(key, from, to).await
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_never_go_back_past_what_the_session_saw() -> Result<(), KvError> {
+        let kv = StaleKV {
+            kv: MemoryKV::new(),
+            stale: Mutex::new(None),
+        };
+        let session = KvSession::new();
+
+        // Nothing seen yet, so the stale read is accepted.
+        assert_eq!(session.read::<_, u32>(&kv, "x").await?, None);
+
+        session.write(&kv, "x", 1).await?;
+        assert_eq!(session.read::<_, u32>(&kv, "x").await?, Some(1));
+
+        kv.kv.write("x", 2).await?;
+        *kv.stale.lock().unwrap() = Some(1);
+        // The stale value is the one the session saw last, so it's accepted.
+        assert_eq!(session.read::<_, u32>(&kv, "x").await?, Some(1));
+        assert_eq!(session.fresh_read::<_, u32>(&kv, "x").await?, Some(2));
+        // Now a read of the stale value is replaced by a fresh read.
+        assert_eq!(session.read::<_, u32>(&kv, "x").await?, Some(2));
+
+        assert!(session.compare_and_swap(&kv, "x", 2, 3).await?);
+        assert_eq!(session.read::<_, u32>(&kv, "x").await?, Some(3));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_cas_doesnt_let_stale_reads_through() -> Result<(), KvError> {
+        let kv = StaleKV {
+            kv: MemoryKV::new(),
+            stale: Mutex::new(Some(1)),
+        };
+        let session = KvSession::new();
+        kv.kv.write("x", 2).await?;
+        assert_eq!(session.fresh_read::<_, u32>(&kv, "x").await?, Some(2));
+
+        assert!(!session.compare_and_swap(&kv, "x", 5, 6).await?);
+        // The session saw 2, so the stale 1 is replaced by a fresh read.
+        assert_eq!(session.read::<_, u32>(&kv, "x").await?, Some(2));
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+use super::{to_json, KvError};
+use crate::{MaelstromErrorCode, RetryPolicy};
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt::Debug;
+use std::time::Duration;
+
+/// The operations shared by Maelstrom's key-value services (`SeqKV`, `LinKV`
+/// and `LwwKV`) and `MemoryKV`, so app logic can be written once and run
+/// against whichever consistency level the app is configured with (or against
+/// `MemoryKV` in tests).
+///
+/// The guarantees are those of the implementation, see each store's docs.
+#[async_trait::async_trait]
+pub trait KeyValueStore: Send + Sync {
+    /// Returns None if the key doesn't exist.
+    async fn read<K, V>(&self, key: K) -> Result<Option<V>, KvError>
+    where
+        K: Serialize + Debug + Clone + Send,
+        V: Serialize + DeserializeOwned + Send;
+
+    async fn write<K, V>(&self, key: K, value: V) -> Result<(), KvError>
+    where
+        K: Serialize + Debug + Send,
+        V: Serialize + Debug + Send;
+
+    /// Sets the key to `to` if its value is `from`, returning false if it
+    /// isn't. A missing key is created with `to`.
+    async fn compare_and_swap<K, V>(&self, key: K, from: V, to: V) -> Result<bool, KvError>
+    where
+        K: Serialize + Debug + Send,
+        V: Serialize + Debug + Send;
+
+    /// Like `read`, but returns the key's current value even if the store
+    /// would otherwise serve a stale one. Stores whose reads are never stale
+    /// just `read`; ones that can't guarantee a current value return an error.
+    async fn fresh_read<K, V>(&self, key: K) -> Result<Option<V>, KvError>
+    where
+        K: Serialize + Debug + Clone + Send,
+        V: Serialize + DeserializeOwned + Send;
+
+    /// How `update` backs off between attempts and how many it makes.
+    fn update_retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::default()
+    }
+
+    /// How long `update` backs off before attempt number `attempt + 1`. The
+    /// default has no jitter, the Maelstrom services' clients take it from
+    /// `MessageWriter::backoff`.
+    fn update_backoff(&self, attempt: u32) -> Duration {
+        self.update_retry_policy().backoff(attempt)
+    }
+
+    /// Atomically replaces the key's value (None if it doesn't exist) with
+    /// `update(value)`, returning the new value. Reads, applies `update` and
+    /// cas's, and starts over (after a backoff) if the value changed in the
+    /// meantime. After `update_retry_policy().max_attempts` failed attempts,
+    /// gives up with a `PreconditionFailed` error.
+    ///
+    /// `update` may be called once per attempt, so it shouldn't have side
+    /// effects.
+    async fn update<K, V, F>(&self, key: K, mut update: F) -> Result<V, KvError>
+    where
+        K: Serialize + Debug + Clone + Send + Sync,
+        V: Serialize + DeserializeOwned + Debug + Send + Sync,
+        F: FnMut(Option<&V>) -> V + Send,
+    {
+        let policy = self.update_retry_policy();
+        let mut attempt = 1;
+        loop {
+            let current = self.read::<K, V>(key.clone()).await?;
+            let new = update(current.as_ref());
+            // A missing key is compared as null, and created by the cas.
+            let from = to_json(&current)?;
+            if self
+                .compare_and_swap(key.clone(), from, to_json(&new)?)
+                .await?
+            {
+                return Ok(new);
+            }
+            if attempt >= policy.max_attempts {
+                return Err(KvError::Service {
+                    code: MaelstromErrorCode::PreconditionFailed,
+                    text: format!("Gave up updating {key:?} after {attempt} attempts"),
+                });
+            }
+            tokio::time::sleep(self.update_backoff(attempt)).await;
+            attempt += 1;
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+use tokio::time::Instant;
+
+#[derive(Debug)]
+pub(crate) enum TimerCommand {
+    Schedule { name: String, deadline: Instant },
+    Cancel { name: String },
+}
+
+/// The app's pending named timers, see `MessageWriter::schedule_timer`.
+#[derive(Debug, Default)]
+pub(crate) struct Timers {
+    deadlines: BTreeMap<String, Instant>,
+}
+
+impl Timers {
+    pub(crate) fn apply(&mut self, command: TimerCommand) {
+        match command {
+            TimerCommand::Schedule { name, deadline } => {
+                self.deadlines.insert(name, deadline);
+            }
+            TimerCommand::Cancel { name } => {
+                self.deadlines.remove(&name);
+            }
+        }
+    }
+
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.deadlines.values().min().copied()
+    }
+
+    /// Removes and returns the timers that are due, earliest first.
+    pub(crate) fn take_due(&mut self, now: Instant) -> Vec<String> {
+        let mut due = self
+            .deadlines
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(name, deadline)| (*deadline, name.clone()))
+            .collect::<Vec<_>>();
+        due.sort();
+        for (_, name) in &due {
+            self.deadlines.remove(name);
+        }
+        due.into_iter().map(|(_, name)| name).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn rescheduled_timers_fire_once_at_their_new_deadline() {
+        let now = Instant::now();
+        let mut timers = Timers::default();
+        for (name, delay) in [("b", 20), ("a", 10), ("b", 5), ("c", 30)] {
+            timers.apply(TimerCommand::Schedule {
+                name: name.to_string(),
+                deadline: now + Duration::from_millis(delay),
+            });
+        }
+        timers.apply(TimerCommand::Cancel {
+            name: "c".to_string(),
+        });
+
+        assert_eq!(timers.next_deadline(), Some(now + Duration::from_millis(5)));
+        assert_eq!(
+            timers.take_due(now + Duration::from_millis(10)),
+            vec!["b".to_string(), "a".to_string()]
+        );
+        assert_eq!(timers.next_deadline(), None);
+    }
+}
@@ -0,0 +1,148 @@
+//! Checks histories of client operations against a workload's consistency
+//! model, like Maelstrom's Jepsen checkers do.
+//!
+//! A `History` is built from the requests clients sent and the responses they
+//! got back. The simulator (`maelstrom::sim`) records one automatically, see
+//! `Simulation::history`.
+
+use crate::{ErrorPayload, Message, MessageID, NodeID, RawMessage};
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+
+mod counter;
+mod linearizable;
+mod set;
+
+pub use self::counter::*;
+pub use self::linearizable::*;
+pub use self::set::*;
+
+/// A client request and (if it completed) its response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operation<TPayload> {
+    /// The client that sent the request, e.g. "c1".
+    pub process: NodeID,
+    /// The node the request was sent to, e.g. "n1".
+    pub node: NodeID,
+    pub request: TPayload,
+    /// None if no response arrived (the operation may or may not have
+    /// happened).
+    pub response: Option<Result<TPayload, ErrorPayload>>,
+    /// Position of the invocation / completion in the history, used to order
+    /// operations in real time.
+    pub invoked_at: usize,
+    pub completed_at: Option<usize>,
+}
+
+impl<TPayload> Operation<TPayload> {
+    pub fn ok_response(&self) -> Option<&TPayload> {
+        match &self.response {
+            Some(Ok(response)) => Some(response),
+            _ => None,
+        }
+    }
+
+    /// The operation definitely did not happen.
+    pub fn is_failed(&self) -> bool {
+        matches!(&self.response, Some(Err(error)) if error.code.is_definite())
+    }
+
+    /// The operation may or may not have happened: it timed out or got an
+    /// indefinite error.
+    pub fn is_indefinite(&self) -> bool {
+        match &self.response {
+            None => true,
+            Some(Err(error)) => !error.code.is_definite(),
+            Some(Ok(_)) => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct History<TPayload> {
+    operations: Vec<Operation<TPayload>>,
+    next_position: usize,
+    /// Operations waiting for a response, keyed by (client, request msg_id).
+    pending: HashMap<(NodeID, MessageID), usize>,
+}
+
+impl<TPayload> Default for History<TPayload> {
+    fn default() -> Self {
+        Self {
+            operations: vec![],
+            next_position: 0,
+            pending: HashMap::new(),
+        }
+    }
+}
+
+impl<TPayload> History<TPayload> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn operations(&self) -> &[Operation<TPayload>] {
+        &self.operations
+    }
+
+    /// Records that `process` sent `request` to `node`, returns the index of the
+    /// new operation to pass to `complete`.
+    pub fn invoke(&mut self, process: NodeID, node: NodeID, request: TPayload) -> usize {
+        self.operations.push(Operation {
+            process,
+            node,
+            request,
+            response: None,
+            invoked_at: self.next_position,
+            completed_at: None,
+        });
+        self.next_position += 1;
+        self.operations.len() - 1
+    }
+
+    /// Records the response to the operation at `index`.
+    pub fn complete(&mut self, index: usize, response: Result<TPayload, ErrorPayload>) {
+        let operation = &mut self.operations[index];
+        operation.response = Some(response);
+        operation.completed_at = Some(self.next_position);
+        self.next_position += 1;
+    }
+
+    /// Records a request a client sent, to be paired with its response by
+    /// `record_response`.
+    pub fn record_request(&mut self, request: &Message<TPayload>) -> anyhow::Result<()>
+    where
+        TPayload: Clone,
+    {
+        let msg_id = request.body.msg_id.context("Request has no msg_id")?;
+        let index = self.invoke(
+            request.src.clone(),
+            request.dst.clone(),
+            request.body.payload.clone(),
+        );
+        self.pending.insert((request.src.clone(), msg_id), index);
+        Ok(())
+    }
+
+    /// Records the response to a request previously passed to
+    /// `record_request`.
+    pub fn record_response(&mut self, response: &RawMessage) -> anyhow::Result<()>
+    where
+        TPayload: DeserializeOwned,
+    {
+        let in_reply_to = response
+            .in_reply_to
+            .context("Response has no in_reply_to")?;
+        let index = self
+            .pending
+            .remove(&(response.dst.clone(), in_reply_to))
+            .with_context(|| format!("No request recorded for response {response:?}"))?;
+        let result = match response.error() {
+            Some(error) => Err(error),
+            None => Ok(response.decode::<TPayload>()?.body.payload),
+        };
+        self.complete(index, result);
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+use super::History;
+use crate::NodeID;
+use std::collections::BTreeMap;
+
+/// Checks a grow-only counter workload, where clients add non-negative deltas
+/// and read the total:
+/// - no read may exceed the sum of every add invoked before it completed,
+/// - once the cluster has quiesced, the last read from every node must be at
+///   least the sum of the acknowledged adds (and at most the sum of every
+///   attempted add).
+///
+/// `delta` returns the amount an add request adds, `value` the total a
+/// response to a read contains.
+pub fn check_counter<TPayload>(
+    history: &History<TPayload>,
+    delta: impl Fn(&TPayload) -> Option<u64>,
+    value: impl Fn(&TPayload) -> Option<u64>,
+) -> anyhow::Result<()> {
+    let mut acknowledged_total = 0;
+    let mut final_reads = BTreeMap::<&NodeID, u64>::new();
+    for operation in history.operations() {
+        if let Some(delta) = delta(&operation.request) {
+            if operation.ok_response().is_some() {
+                acknowledged_total += delta;
+            }
+        }
+
+        let Some(read) = operation.ok_response().and_then(&value) else {
+            continue;
+        };
+        let completed_at = operation.completed_at.expect("ok operations completed");
+        let possible_total = history
+            .operations()
+            .iter()
+            .filter(|other| other.invoked_at < completed_at && !other.is_failed())
+            .filter_map(|other| delta(&other.request))
+            .sum::<u64>();
+        if read > possible_total {
+            anyhow::bail!(
+                "{:?} read {read}, but at most {possible_total} had been added.",
+                operation.node
+            );
+        }
+        final_reads.insert(&operation.node, read);
+    }
+
+    if final_reads.is_empty() {
+        anyhow::bail!("History contains no successful reads.");
+    }
+    for (node_id, read) in final_reads {
+        if read < acknowledged_total {
+            anyhow::bail!("{node_id:?} read {read}, expected at least {acknowledged_total}.");
+        }
+    }
+    Ok(())
+}
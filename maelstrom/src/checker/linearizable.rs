@@ -0,0 +1,232 @@
+use super::History;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// A key-value operation, as seen by the client that performed it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KvOperation<K, V> {
+    /// `value` is what the read returned, None if the key didn't exist.
+    Read {
+        key: K,
+        value: Option<V>,
+    },
+    Write {
+        key: K,
+        value: V,
+    },
+    CompareAndSwap {
+        key: K,
+        from: V,
+        to: V,
+    },
+}
+
+struct RegisterOperation<V> {
+    operation: KvOperation<(), V>,
+    invoked_at: usize,
+    /// None for indefinite operations, which may take effect at any point after
+    /// their invocation, or never.
+    completed_at: Option<usize>,
+}
+
+/// Checks that the key-value operations in `history` are linearizable: that
+/// there is a single order of all operations, consistent with real time, in
+/// which every read sees the latest write.
+///
+/// `to_kv_operation` interprets an operation from its request and response
+/// (None when the request wasn't answered), it should return None for
+/// operations that aren't key-value operations. Reads without a response and
+/// operations that definitely failed are ignored.
+pub fn check_linearizable_kv<TPayload, K, V>(
+    history: &History<TPayload>,
+    to_kv_operation: impl Fn(&TPayload, Option<&TPayload>) -> Option<KvOperation<K, V>>,
+) -> anyhow::Result<()>
+where
+    K: Ord + Debug,
+    V: Clone + Eq + Hash + Debug,
+{
+    // Linearizability is local, so each key can be checked on its own.
+    let mut operations_by_key = BTreeMap::<K, Vec<RegisterOperation<V>>>::new();
+    for operation in history.operations() {
+        if operation.is_failed() {
+            continue;
+        }
+        let Some(kv_operation) = to_kv_operation(&operation.request, operation.ok_response())
+        else {
+            continue;
+        };
+        let completed_at = if operation.is_indefinite() {
+            if matches!(kv_operation, KvOperation::Read { .. }) {
+                continue;
+            }
+            None
+        } else {
+            operation.completed_at
+        };
+
+        let (key, register_operation) = match kv_operation {
+            KvOperation::Read { key, value } => (key, KvOperation::Read { key: (), value }),
+            KvOperation::Write { key, value } => (key, KvOperation::Write { key: (), value }),
+            KvOperation::CompareAndSwap { key, from, to } => {
+                (key, KvOperation::CompareAndSwap { key: (), from, to })
+            }
+        };
+        operations_by_key
+            .entry(key)
+            .or_default()
+            .push(RegisterOperation {
+                operation: register_operation,
+                invoked_at: operation.invoked_at,
+                completed_at,
+            });
+    }
+
+    for (key, operations) in operations_by_key {
+        if !is_linearizable_register(&operations) {
+            anyhow::bail!("Operations on key {key:?} are not linearizable.");
+        }
+    }
+    Ok(())
+}
+
+/// Wing & Gong's search: repeatedly pick an operation that could take effect
+/// next (no other required operation completed before it was invoked), apply
+/// it to the register, and backtrack on dead ends.
+fn is_linearizable_register<V: Clone + Eq + Hash>(operations: &[RegisterOperation<V>]) -> bool {
+    let mut linearized = vec![false; operations.len()];
+    let mut visited = HashSet::new();
+    search(operations, &mut linearized, None, &mut visited)
+}
+
+fn search<V: Clone + Eq + Hash>(
+    operations: &[RegisterOperation<V>],
+    linearized: &mut Vec<bool>,
+    value: Option<V>,
+    visited: &mut HashSet<(Vec<bool>, Option<V>)>,
+) -> bool {
+    let next_deadline = operations
+        .iter()
+        .zip(linearized.iter())
+        .filter(|(_, linearized)| !**linearized)
+        .filter_map(|(operation, _)| operation.completed_at)
+        .min();
+    let Some(next_deadline) = next_deadline else {
+        // Every operation that completed has been linearized, indefinite
+        // operations are allowed to never take effect.
+        return true;
+    };
+    if !visited.insert((linearized.clone(), value.clone())) {
+        return false;
+    }
+
+    for (index, operation) in operations.iter().enumerate() {
+        if linearized[index] || operation.invoked_at > next_deadline {
+            continue;
+        }
+        let next_value = match &operation.operation {
+            KvOperation::Read { value: read, .. } if *read == value => value.clone(),
+            KvOperation::Read { .. } => continue,
+            KvOperation::Write { value: written, .. } => Some(written.clone()),
+            KvOperation::CompareAndSwap { from, to, .. } if value.as_ref() == Some(from) => {
+                Some(to.clone())
+            }
+            KvOperation::CompareAndSwap { .. } => continue,
+        };
+
+        linearized[index] = true;
+        if search(operations, linearized, next_value, visited) {
+            return true;
+        }
+        linearized[index] = false;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorPayload, MaelstromErrorCode};
+
+    type Op = KvOperation<&'static str, u32>;
+
+    fn check(history: &History<Op>) -> anyhow::Result<()> {
+        check_linearizable_kv(history, |request, response| match (request, response) {
+            (KvOperation::Read { key, .. }, Some(KvOperation::Read { value, .. })) => {
+                Some(KvOperation::Read {
+                    key: *key,
+                    value: *value,
+                })
+            }
+            (KvOperation::Read { .. }, _) => None,
+            (operation, _) => Some(operation.clone()),
+        })
+    }
+
+    fn read(value: Option<u32>) -> Op {
+        KvOperation::Read { key: "x", value }
+    }
+
+    fn write(value: u32) -> Op {
+        KvOperation::Write { key: "x", value }
+    }
+
+    fn invoke(history: &mut History<Op>, operation: Op) -> usize {
+        history.invoke("c1".into(), "n1".into(), operation)
+    }
+
+    #[test]
+    fn accepts_concurrent_operations_in_either_order() {
+        // The write overlaps both reads, so it may land between them.
+        let mut history = History::new();
+        let write_1 = invoke(&mut history, write(1));
+        let first_read = invoke(&mut history, read(None));
+        history.complete(first_read, Ok(read(None)));
+        let second_read = invoke(&mut history, read(None));
+        history.complete(second_read, Ok(read(Some(1))));
+        history.complete(write_1, Ok(write(1)));
+        check(&history).expect("linearizable");
+    }
+
+    #[test]
+    fn rejects_stale_reads() {
+        let mut history = History::new();
+        for value in [1, 2] {
+            let index = invoke(&mut history, write(value));
+            history.complete(index, Ok(write(value)));
+        }
+        let index = invoke(&mut history, read(None));
+        history.complete(index, Ok(read(Some(1))));
+        assert!(check(&history).is_err());
+    }
+
+    #[test]
+    fn indefinite_writes_may_or_may_not_happen() {
+        for read_value in [Some(1), None] {
+            let mut history = History::new();
+            let index = invoke(&mut history, write(1));
+            history.complete(
+                index,
+                Err(ErrorPayload::new(MaelstromErrorCode::Timeout, "")),
+            );
+            let index = invoke(&mut history, read(None));
+            history.complete(index, Ok(read(read_value)));
+            check(&history).expect("linearizable");
+        }
+    }
+
+    #[test]
+    fn compare_and_swap_needs_the_expected_value() {
+        let cas = KvOperation::CompareAndSwap {
+            key: "x",
+            from: 2,
+            to: 3,
+        };
+        let mut history = History::new();
+        let index = invoke(&mut history, write(1));
+        history.complete(index, Ok(write(1)));
+        let index = invoke(&mut history, cas.clone());
+        history.complete(index, Ok(cas));
+        assert!(check(&history).is_err());
+    }
+}
@@ -0,0 +1,57 @@
+use super::History;
+use crate::NodeID;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// Checks a broadcast-style workload, where clients add elements to a set and
+/// read the whole set back. Once the cluster has quiesced, the last read from
+/// every node must contain every acknowledged element, nothing that was never
+/// added, and be the same on every node.
+///
+/// `added` returns the element a request adds, `read` the elements a response
+/// to a read contains.
+pub fn check_set_convergence<TPayload, T>(
+    history: &History<TPayload>,
+    added: impl Fn(&TPayload) -> Option<T>,
+    read: impl Fn(&TPayload) -> Option<Vec<T>>,
+) -> anyhow::Result<()>
+where
+    T: Clone + Eq + Hash + Debug,
+{
+    let mut attempted = HashSet::new();
+    let mut acknowledged = HashSet::new();
+    let mut final_reads = BTreeMap::<&NodeID, HashSet<T>>::new();
+    for operation in history.operations() {
+        if let Some(element) = added(&operation.request) {
+            if operation.ok_response().is_some() {
+                acknowledged.insert(element.clone());
+            }
+            attempted.insert(element);
+        }
+        if let Some(elements) = operation.ok_response().and_then(&read) {
+            final_reads.insert(&operation.node, elements.into_iter().collect());
+        }
+    }
+
+    if final_reads.is_empty() {
+        anyhow::bail!("History contains no successful reads.");
+    }
+    for (node_id, elements) in &final_reads {
+        let lost = acknowledged.difference(elements).collect::<Vec<_>>();
+        if !lost.is_empty() {
+            anyhow::bail!("{node_id:?} lost acknowledged elements: {lost:?}.");
+        }
+        let unexpected = elements.difference(&attempted).collect::<Vec<_>>();
+        if !unexpected.is_empty() {
+            anyhow::bail!("{node_id:?} read elements that were never added: {unexpected:?}.");
+        }
+    }
+    let (first_node_id, first_elements) = final_reads.iter().next().expect("not empty");
+    for (node_id, elements) in &final_reads {
+        if elements != first_elements {
+            anyhow::bail!("{node_id:?} did not converge with {first_node_id:?}.");
+        }
+    }
+    Ok(())
+}
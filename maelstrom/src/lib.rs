@@ -1,5 +1,17 @@
+// Lets the code generated by `#[maelstrom::payload]` work inside this crate.
+extern crate self as maelstrom;
+
 mod app;
+pub mod checker;
+mod logging;
+mod metrics;
+mod payload;
 mod protocol;
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
 
 pub use self::app::*;
+pub use self::logging::*;
+pub use self::metrics::*;
+pub use self::payload::*;
 pub use self::protocol::*;
@@ -0,0 +1,26 @@
+use tracing_subscriber::EnvFilter;
+
+/// Filter directives for the logs, e.g. `info` or `warn,maelstrom::app=debug`.
+/// Defaults to `info`.
+pub const LOG_FILTER_ENV_VAR: &str = "MAELSTROM_LOG";
+/// Set to `json` to log one JSON object per line instead of plain text.
+pub const LOG_FORMAT_ENV_VAR: &str = "MAELSTROM_LOG_FORMAT";
+
+/// Sends logs to stderr (stdout is reserved for Maelstrom messages), configured
+/// by the `MAELSTROM_LOG` and `MAELSTROM_LOG_FORMAT` environment variables.
+/// Does nothing if logging was already set up.
+pub fn init_logging() {
+    let filter =
+        EnvFilter::try_from_env(LOG_FILTER_ENV_VAR).unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        // Maelstrom collects stderr into log files.
+        .with_ansi(false);
+    let result = match std::env::var(LOG_FORMAT_ENV_VAR).as_deref() {
+        Ok("json") => builder.json().try_init(),
+        _ => builder.try_init(),
+    };
+    // Only fails if a subscriber was already set, which is fine.
+    let _ = result;
+}
@@ -0,0 +1,276 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::NodeID;
+
+/// Counters and histograms collected by the runtime, from the node's own point
+/// of view. Logged when the node exits (or gets `SIGUSR1`), and readable by the
+/// app through `MessageWriter::metrics`.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Updated for every message, so under their own locks.
+    sent: Mutex<MessageCounts<&'static str>>,
+    received: Mutex<MessageCounts<String>>,
+    /// Everything else, the message counts are filled in by `snapshot`.
+    snapshot: Mutex<MetricsSnapshot>,
+}
+
+#[derive(Debug)]
+struct MessageCounts<TType> {
+    by_type: BTreeMap<TType, u64>,
+    by_peer: BTreeMap<String, u64>,
+}
+
+impl<TType> Default for MessageCounts<TType> {
+    fn default() -> Self {
+        Self {
+            by_type: BTreeMap::new(),
+            by_peer: BTreeMap::new(),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let mut snapshot = self.snapshot.lock().expect("not poisoned").clone();
+        {
+            let sent = self.sent.lock().expect("not poisoned");
+            snapshot.sent_by_type = sent
+                .by_type
+                .iter()
+                .map(|(message_type, count)| (message_type.to_string(), *count))
+                .collect();
+            snapshot.sent_by_peer = sent.by_peer.clone();
+        }
+        let received = self.received.lock().expect("not poisoned");
+        snapshot.received_by_type = received.by_type.clone();
+        snapshot.received_by_peer = received.by_peer.clone();
+        snapshot
+    }
+
+    /// Writes the summary straight to stderr rather than logging it, so that
+    /// `MAELSTROM_LOG` can't filter it out.
+    pub(crate) fn log_summary(&self) {
+        let _ = write!(io::stderr().lock(), "Metrics summary:\n{}", self.snapshot());
+    }
+
+    pub(crate) fn record_sent(&self, dst: &NodeID, message_type: &'static str) {
+        let mut counts = self.sent.lock().expect("not poisoned");
+        *counts.by_type.entry(message_type).or_default() += 1;
+        increment(&mut counts.by_peer, dst);
+    }
+
+    /// Types of received messages aren't known ahead of time, so they're only
+    /// copied the first time they're seen.
+    pub(crate) fn record_received(&self, src: &NodeID, message_type: &str) {
+        let mut counts = self.received.lock().expect("not poisoned");
+        increment(&mut counts.by_type, message_type);
+        increment(&mut counts.by_peer, src);
+    }
+
+    pub(crate) fn record_rpc_latency(&self, latency: Duration) {
+        let mut snapshot = self.snapshot.lock().expect("not poisoned");
+        snapshot.rpc_latency.record(latency);
+    }
+
+    pub(crate) fn record_handler_duration(&self, duration: Duration) {
+        let mut snapshot = self.snapshot.lock().expect("not poisoned");
+        snapshot.handler_duration.record(duration);
+    }
+
+    pub(crate) fn record_queue_capacity(&self, queue: &'static str, capacity: usize) {
+        let mut snapshot = self.snapshot.lock().expect("not poisoned");
+        snapshot.queues.entry(queue).or_default().capacity = capacity;
+    }
+
+    /// Records how many messages were waiting in the `queue`.
+    pub(crate) fn record_queue_depth(&self, queue: &'static str, depth: usize) {
+        let mut snapshot = self.snapshot.lock().expect("not poisoned");
+        let stats = snapshot.queues.entry(queue).or_default();
+        stats.max_depth = stats.max_depth.max(depth);
+    }
+
+    /// Records a message dropped (or shed) because the `queue` was full.
+    pub(crate) fn record_dropped(&self, queue: &'static str) {
+        let mut snapshot = self.snapshot.lock().expect("not poisoned");
+        snapshot.queues.entry(queue).or_default().dropped += 1;
+    }
+}
+
+/// Counts one more under `key`, copying it only the first time it's seen.
+fn increment(counts: &mut BTreeMap<String, u64>, key: &str) {
+    match counts.get_mut(key) {
+        Some(count) => *count += 1,
+        None => {
+            counts.insert(key.to_string(), 1);
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MetricsSnapshot {
+    /// Keyed by the payload's `type`.
+    pub sent_by_type: BTreeMap<String, u64>,
+    pub sent_by_peer: BTreeMap<String, u64>,
+    pub received_by_type: BTreeMap<String, u64>,
+    pub received_by_peer: BTreeMap<String, u64>,
+    /// Time from sending an RPC until its response arrived. RPCs that timed
+    /// out aren't included.
+    pub rpc_latency: Histogram,
+    /// Time `App::handle` (or `ConcurrentApp::handle`) took per message.
+    pub handler_duration: Histogram,
+    /// The runtime's queues ("incoming", "app" and "outgoing"), see
+    /// `QueueConfig`.
+    pub queues: BTreeMap<&'static str, QueueStats>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct QueueStats {
+    /// Zero if the runtime didn't record it, e.g. for queues of a simulation.
+    /// For the outgoing queue, its soft limit.
+    pub capacity: usize,
+    /// The most messages seen waiting in the queue.
+    pub max_depth: usize,
+    /// Messages dropped (or shed) because the queue was full.
+    pub dropped: u64,
+}
+
+impl fmt::Display for MetricsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counts = [
+            ("sent by type", &self.sent_by_type),
+            ("sent by peer", &self.sent_by_peer),
+            ("received by type", &self.received_by_type),
+            ("received by peer", &self.received_by_peer),
+        ];
+        for (name, counts) in counts {
+            writeln!(f, "{name}: {}", counts.values().sum::<u64>())?;
+            for (key, count) in counts {
+                writeln!(f, "  {key}: {count}")?;
+            }
+        }
+        writeln!(f, "rpc latency: {}", self.rpc_latency)?;
+        writeln!(f, "handler duration: {}", self.handler_duration)?;
+        writeln!(f, "queues:")?;
+        for (queue, stats) in &self.queues {
+            writeln!(
+                f,
+                "  {queue}: max depth {} of {}, {} dropped",
+                stats.max_depth, stats.capacity, stats.dropped
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Durations bucketed by powers of two microseconds, so quantiles are only
+/// accurate to within a factor of two.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Histogram {
+    /// `buckets[i]` counts durations under `2^i` microseconds (and at least
+    /// `2^(i - 1)`).
+    buckets: Vec<u64>,
+    count: u64,
+    total: Duration,
+    max: Duration,
+}
+
+impl Histogram {
+    pub fn record(&mut self, duration: Duration) {
+        let micros = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        if self.buckets.len() <= bucket {
+            self.buckets.resize(bucket + 1, 0);
+        }
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.total += duration;
+        self.max = self.max.max(duration);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            count => self.total / u32::try_from(count).unwrap_or(u32::MAX),
+        }
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// An upper bound on the `quantile` (between 0 and 1) of the durations.
+    pub fn quantile(&self, quantile: f64) -> Duration {
+        let target = (quantile * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return Duration::from_micros(1 << bucket).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "count={} mean={:?} p50<={:?} p99<={:?} max={:?}",
+            self.count,
+            self.mean(),
+            self.quantile(0.5),
+            self.quantile(0.99),
+            self.max,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_quantiles_are_upper_bounds() {
+        let mut histogram = Histogram::default();
+        for millis in 1..=100 {
+            histogram.record(Duration::from_millis(millis));
+        }
+
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.mean(), Duration::from_micros(50_500));
+        assert_eq!(histogram.max(), Duration::from_millis(100));
+        let p50 = histogram.quantile(0.5);
+        assert!(p50 >= Duration::from_millis(50) && p50 < Duration::from_millis(100));
+        assert_eq!(histogram.quantile(1.0), Duration::from_millis(100));
+        assert_eq!(Histogram::default().quantile(0.5), Duration::ZERO);
+    }
+
+    #[test]
+    fn metrics_count_messages_by_type_and_peer() {
+        let metrics = Metrics::default();
+        metrics.record_sent(&"n1".into(), "gossip");
+        metrics.record_sent(&"n2".into(), "gossip");
+        metrics.record_received(&"c1".into(), "broadcast");
+        metrics.record_queue_capacity("incoming", 10);
+        metrics.record_queue_depth("incoming", 3);
+        metrics.record_queue_depth("incoming", 1);
+        metrics.record_dropped("incoming");
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.sent_by_type["gossip"], 2);
+        assert_eq!(snapshot.sent_by_peer["n1"], 1);
+        assert_eq!(snapshot.received_by_peer["c1"], 1);
+        let summary = snapshot.to_string();
+        assert!(summary.contains("sent by type: 2\n  gossip: 2\n"));
+        assert!(summary.contains("incoming: max depth 3 of 10, 1 dropped"));
+    }
+}
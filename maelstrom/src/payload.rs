@@ -0,0 +1,130 @@
+/// Turns an enum into a Maelstrom payload type, with the serde attributes every
+/// payload needs (the variant's snake_case name as the body's `type`). Adds an
+/// `Error { code, text }` variant for `error` bodies, unless the enum has its
+/// own, and implements `MaelstromPayload` and `PayloadType`.
+///
+/// A variant named like another one plus `Ok` is its reply, e.g. `EchoOk` for
+/// `Echo`. Every other variant is a request. The runtime uses this to answer a
+/// request the app couldn't decode with a malformed-request error, rather
+/// than a not-supported one.
+///
+/// It doesn't implement `Rpc`, since a single enum can't say which variant
+/// answers which request. Use `MessageWriter::send_and_receive` to send one of
+/// its requests, or a dedicated request struct with its own `Rpc` impl (like
+/// `Ts`) to get a typed response from `call`.
+///
+/// ```
+/// #[maelstrom::payload]
+/// #[derive(Debug, PartialEq, Clone)]
+/// enum EchoPayload {
+///     Echo { echo: String },
+///     EchoOk { echo: String },
+/// }
+/// ```
+///
+/// It's an attribute rather than a derive because a derive can't add
+/// attributes or variants to the enum.
+pub use maelstrom_macros::payload;
+
+/// A payload that knows its own `type`, so that the runtime can count the
+/// messages it sends by type without looking into their JSON. Everything sent
+/// through a `MessageWriter` implements it.
+pub trait PayloadType {
+    /// The `type` of this payload, e.g. "echo_ok".
+    fn payload_type(&self) -> &'static str;
+}
+
+/// What `#[maelstrom::payload]` knows about a payload enum's variants. The
+/// runtime requires it of every app's payload, see `App::strict_payloads`.
+pub trait MaelstromPayload: PayloadType {
+    /// The `type` of every request, with the `type` of its `_ok` reply if it
+    /// has one.
+    const REQUESTS: &'static [(&'static str, Option<&'static str>)];
+
+    /// Whether this payload is a reply to a request: an `_ok` or an `error`.
+    fn is_response(&self) -> bool;
+
+    /// Whether `payload_type` is the `type` of one of the requests.
+    fn is_request_type(payload_type: &str) -> bool {
+        Self::REQUESTS
+            .iter()
+            .any(|(request, _)| *request == payload_type)
+    }
+
+    /// The `type` of the reply to a request of type `request_type`, if it gets
+    /// one.
+    fn reply_type(request_type: &str) -> Option<&'static str> {
+        Self::REQUESTS
+            .iter()
+            .find(|(request, _)| *request == request_type)
+            .and_then(|(_, reply)| *reply)
+    }
+}
+
+/// Used by the code `#[maelstrom::payload]` generates, so that crates using it
+/// don't need their own serde dependencies.
+#[doc(hidden)]
+pub mod __private {
+    pub use serde;
+    pub use serde_derive;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorPayload, MaelstromErrorCode};
+    use serde_json::json;
+
+    #[payload]
+    #[derive(Debug, PartialEq, Clone)]
+    enum TestPayload {
+        Add {
+            delta: u32,
+        },
+        AddOk,
+        #[serde(rename = "txn")]
+        Transaction,
+        TransactionOk,
+        Gossip {
+            messages: Vec<u32>,
+        },
+    }
+
+    #[test]
+    fn payloads_are_tagged_by_snake_case_type() {
+        assert_eq!(
+            serde_json::to_value(TestPayload::Add { delta: 1 }).unwrap(),
+            json!({"type": "add", "delta": 1})
+        );
+        assert_eq!(
+            serde_json::from_value::<TestPayload>(json!({"type": "transaction_ok"})).unwrap(),
+            TestPayload::TransactionOk
+        );
+        let error = ErrorPayload::new(MaelstromErrorCode::Abort, "no");
+        assert_eq!(
+            serde_json::from_value::<TestPayload>(serde_json::to_value(error).unwrap()).unwrap(),
+            TestPayload::Error {
+                code: MaelstromErrorCode::Abort,
+                text: "no".into()
+            }
+        );
+    }
+
+    #[test]
+    fn requests_are_paired_with_their_replies() {
+        assert_eq!(
+            TestPayload::REQUESTS,
+            &[
+                ("add", Some("add_ok")),
+                ("txn", Some("transaction_ok")),
+                ("gossip", None),
+            ]
+        );
+        assert_eq!(TestPayload::reply_type("txn"), Some("transaction_ok"));
+        assert!(TestPayload::is_request_type("gossip"));
+        assert!(!TestPayload::is_request_type("add_ok"));
+        assert_eq!(TestPayload::Transaction.payload_type(), "txn");
+        assert!(TestPayload::AddOk.is_response());
+        assert!(!TestPayload::Gossip { messages: vec![] }.is_response());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::PayloadType;
 use anyhow::Context;
 use serde::de::DeserializeOwned;
 
@@ -27,6 +28,8 @@ impl From<u32> for MessageID {
     Debug,
     PartialEq,
     Eq,
+    PartialOrd,
+    Ord,
     Hash,
     Clone,
     shrinkwraprs::Shrinkwrap,
@@ -103,6 +106,77 @@ impl MessageBody<serde_json::Value> {
     }
 }
 
+/// A message as read off the wire, decoded lazily: only the envelope and the
+/// payload's `type` are parsed up front, which is enough to route the message.
+/// The payload is then deserialized straight from the line into the type the
+/// receiver expects, without building a `serde_json::Value` first.
+#[derive(Debug, Clone)]
+pub struct RawMessage {
+    line: String,
+    pub src: NodeID,
+    pub dst: NodeID,
+    pub msg_id: Option<MessageID>,
+    pub in_reply_to: Option<MessageID>,
+    payload_type: Option<String>,
+}
+
+/// The parts of a message `RawMessage` parses up front, the rest of the line
+/// is skipped without being allocated.
+#[derive(serde_derive::Deserialize)]
+struct Envelope {
+    src: NodeID,
+    dest: NodeID,
+    body: EnvelopeBody,
+}
+
+#[derive(serde_derive::Deserialize)]
+struct EnvelopeBody {
+    msg_id: Option<MessageID>,
+    in_reply_to: Option<MessageID>,
+    #[serde(rename = "type")]
+    payload_type: Option<String>,
+}
+
+impl RawMessage {
+    pub fn parse(line: String) -> anyhow::Result<Self> {
+        let envelope =
+            serde_json::from_str::<Envelope>(&line).context("Couldn't deserialize Message")?;
+        Ok(Self {
+            line,
+            src: envelope.src,
+            dst: envelope.dest,
+            msg_id: envelope.body.msg_id,
+            in_reply_to: envelope.body.in_reply_to,
+            payload_type: envelope.body.payload_type,
+        })
+    }
+
+    /// The payload's `type`, e.g. "echo_ok".
+    pub fn payload_type(&self) -> Option<&str> {
+        self.payload_type.as_deref()
+    }
+
+    /// The message as it was received.
+    pub fn as_str(&self) -> &str {
+        &self.line
+    }
+
+    pub fn decode<TPayload: DeserializeOwned>(&self) -> anyhow::Result<Message<TPayload>> {
+        serde_json::from_str(&self.line).context("Couldn't convert payload type!")
+    }
+
+    /// Decodes the payload as an `error`, returns None if it is a different
+    /// type of payload.
+    pub fn error(&self) -> Option<ErrorPayload> {
+        if self.payload_type() != Some("error") {
+            return None;
+        }
+        self.decode::<ErrorPayload>()
+            .ok()
+            .map(|message| message.body.payload)
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
@@ -114,9 +188,164 @@ pub enum InitPayload {
     InitOk,
 }
 
+impl PayloadType for InitPayload {
+    fn payload_type(&self) -> &'static str {
+        match self {
+            InitPayload::Init { .. } => "init",
+            InitPayload::InitOk => "init_ok",
+        }
+    }
+}
+
+/// The error codes defined by Maelstrom. Codes that Maelstrom doesn't define
+/// (e.g. app-specific codes, which should be 1000 and above) are kept as
+/// `Custom`. Codes compare by number, so `Custom(22)` is `PreconditionFailed`.
+///
+/// https://github.com/jepsen-io/maelstrom/blob/main/doc/protocol.md#errors
+#[derive(Debug, Clone, Copy, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(from = "u32", into = "u32")]
+pub enum MaelstromErrorCode {
+    /// "Indicates that the requested operation could not be completed within a
+    /// timeout."
+    Timeout,
+    /// "Thrown when a client sends an RPC request to a node which does not
+    /// exist."
+    NodeNotFound,
+    /// "Use this error to indicate that a requested operation is not supported
+    /// by the current implementation."
+    NotSupported,
+    /// "Indicates that the operation definitely cannot be performed at this
+    /// time--perhaps because the server is in a read-only state, has not yet
+    /// been initialized, believes its peers to be down, and so on."
+    TemporarilyUnavailable,
+    /// "The client's request did not conform to the server's expectations, and
+    /// could not possibly have been processed."
+    MalformedRequest,
+    /// "Indicates that some kind of general, indefinite error occurred."
+    Crash,
+    /// "Indicates that some kind of general, definite error occurred."
+    Abort,
+    /// "The client requested an operation on a key which does not exist."
+    KeyDoesNotExist,
+    /// "The client requested the creation of a key which already exists, and
+    /// the server will not overwrite it."
+    KeyAlreadyExists,
+    /// "The requested operation expected some conditions to hold, and those
+    /// conditions were not met."
+    PreconditionFailed,
+    /// "The requested transaction has been aborted because of a conflict with
+    /// another transaction."
+    TxnConflict,
+    Custom(u32),
+}
+
+impl MaelstromErrorCode {
+    pub fn code(&self) -> u32 {
+        match self {
+            MaelstromErrorCode::Timeout => 0,
+            MaelstromErrorCode::NodeNotFound => 1,
+            MaelstromErrorCode::NotSupported => 10,
+            MaelstromErrorCode::TemporarilyUnavailable => 11,
+            MaelstromErrorCode::MalformedRequest => 12,
+            MaelstromErrorCode::Crash => 13,
+            MaelstromErrorCode::Abort => 14,
+            MaelstromErrorCode::KeyDoesNotExist => 20,
+            MaelstromErrorCode::KeyAlreadyExists => 21,
+            MaelstromErrorCode::PreconditionFailed => 22,
+            MaelstromErrorCode::TxnConflict => 30,
+            MaelstromErrorCode::Custom(code) => *code,
+        }
+    }
+
+    /// Definite errors mean the operation definitely did not happen, indefinite
+    /// errors mean it may or may not have happened. Custom codes are treated as
+    /// indefinite since nothing is known about them.
+    pub fn is_definite(&self) -> bool {
+        !matches!(
+            MaelstromErrorCode::from(self.code()),
+            MaelstromErrorCode::Timeout | MaelstromErrorCode::Crash | MaelstromErrorCode::Custom(_)
+        )
+    }
+}
+
+impl PartialEq for MaelstromErrorCode {
+    fn eq(&self, other: &Self) -> bool {
+        self.code() == other.code()
+    }
+}
+
+impl Eq for MaelstromErrorCode {}
+
+impl std::hash::Hash for MaelstromErrorCode {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.code().hash(state);
+    }
+}
+
+impl From<u32> for MaelstromErrorCode {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => MaelstromErrorCode::Timeout,
+            1 => MaelstromErrorCode::NodeNotFound,
+            10 => MaelstromErrorCode::NotSupported,
+            11 => MaelstromErrorCode::TemporarilyUnavailable,
+            12 => MaelstromErrorCode::MalformedRequest,
+            13 => MaelstromErrorCode::Crash,
+            14 => MaelstromErrorCode::Abort,
+            20 => MaelstromErrorCode::KeyDoesNotExist,
+            21 => MaelstromErrorCode::KeyAlreadyExists,
+            22 => MaelstromErrorCode::PreconditionFailed,
+            30 => MaelstromErrorCode::TxnConflict,
+            code => MaelstromErrorCode::Custom(code),
+        }
+    }
+}
+
+impl From<MaelstromErrorCode> for u32 {
+    fn from(value: MaelstromErrorCode) -> Self {
+        value.code()
+    }
+}
+
+/// The body of a Maelstrom `error` message, e.g.
+/// `{"type": "error", "code": 20, "text": "not found"}`.
+///
+/// Also usable as an error, so apps can return it from their handlers.
+#[derive(Debug, PartialEq, Eq, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(tag = "type", rename = "error")]
+pub struct ErrorPayload {
+    pub code: MaelstromErrorCode,
+    #[serde(default)]
+    pub text: String,
+}
+
+impl ErrorPayload {
+    pub fn new(code: MaelstromErrorCode, text: impl Into<String>) -> Self {
+        Self {
+            code,
+            text: text.into(),
+        }
+    }
+}
+
+impl PayloadType for ErrorPayload {
+    fn payload_type(&self) -> &'static str {
+        "error"
+    }
+}
+
+impl std::fmt::Display for ErrorPayload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Maelstrom error {:?}: {}", self.code, self.text)
+    }
+}
+
+impl std::error::Error for ErrorPayload {}
+
 #[cfg(test)]
 mod tests {
     use indoc::indoc;
+    use std::collections::HashSet;
 
     use super::*;
 
@@ -136,7 +365,7 @@ mod tests {
 			}
 		"##
         );
-        let message: Message<InitPayload> = serde_json::from_str(&message_json).expect("works");
+        let message: Message<InitPayload> = serde_json::from_str(message_json).expect("works");
         assert_eq!(
             message,
             Message {
@@ -153,4 +382,68 @@ mod tests {
             }
         )
     }
+
+    #[test]
+    fn raw_messages_decode_lazily() {
+        let line = r#"{"src": "n1", "dest": "n2", "body": {"type": "error", "in_reply_to": 3, "code": 11}}"#;
+        let message = RawMessage::parse(line.to_string()).expect("works");
+        assert_eq!((message.src.as_str(), message.dst.as_str()), ("n1", "n2"));
+        assert_eq!(
+            (message.msg_id, message.in_reply_to),
+            (None, Some(3.into()))
+        );
+        assert_eq!(message.payload_type(), Some("error"));
+        assert_eq!(
+            message.error(),
+            Some(ErrorPayload::new(
+                MaelstromErrorCode::TemporarilyUnavailable,
+                ""
+            ))
+        );
+        assert!(message.decode::<InitPayload>().is_err());
+
+        assert!(RawMessage::parse("not json".to_string()).is_err());
+    }
+
+    #[test]
+    fn error_payload_round_trips_codes() {
+        let payload: ErrorPayload =
+            serde_json::from_str(r#"{"type": "error", "code": 22, "text": "nope"}"#)
+                .expect("works");
+        assert_eq!(
+            payload,
+            ErrorPayload::new(MaelstromErrorCode::PreconditionFailed, "nope")
+        );
+        assert_eq!(
+            serde_json::to_value(ErrorPayload::new(MaelstromErrorCode::Custom(1001), "app"))
+                .expect("works"),
+            serde_json::json!({"type": "error", "code": 1001, "text": "app"})
+        );
+    }
+
+    #[test]
+    fn custom_codes_equal_the_codes_maelstrom_defines() {
+        let custom = MaelstromErrorCode::Custom(22);
+        assert_eq!(custom, MaelstromErrorCode::PreconditionFailed);
+        assert!(custom.is_definite());
+        assert!([MaelstromErrorCode::PreconditionFailed]
+            .into_iter()
+            .collect::<HashSet<_>>()
+            .contains(&custom));
+        assert_ne!(
+            MaelstromErrorCode::Custom(1000),
+            MaelstromErrorCode::Custom(1001)
+        );
+    }
+
+    #[test]
+    fn error_codes_know_if_they_are_definite() {
+        assert!(!MaelstromErrorCode::from(0).is_definite());
+        assert!(!MaelstromErrorCode::from(13).is_definite());
+        assert!(MaelstromErrorCode::from(22).is_definite());
+        assert_eq!(
+            MaelstromErrorCode::from(30),
+            MaelstromErrorCode::TxnConflict
+        );
+    }
 }
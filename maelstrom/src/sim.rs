@@ -0,0 +1,407 @@
+//! Runs a cluster of `App`s inside a single process, for tests.
+//!
+//! Nodes exchange messages through a simulated network instead of
+//! stdin/stdout. Time is virtual (the tokio clock is paused and skips ahead
+//! whenever every node is idle) and all randomness, the network's and the
+//! jitter of every node's retries, comes from RNGs seeded with
+//! `SimConfig::seed`. So a simulation behaves the same way on every run as long
+//! as the app itself is deterministic.
+//!
+//! ```
+//! use maelstrom::sim::{SimConfig, Simulation};
+//! use maelstrom::{App, Message, MessageWriter, NodeID};
+//!
+//! #[maelstrom::payload]
+//! #[derive(Debug, PartialEq, Clone)]
+//! enum EchoPayload {
+//!     Echo { echo: String },
+//!     EchoOk { echo: String },
+//! }
+//!
+//! struct Echo;
+//!
+//! #[async_trait::async_trait]
+//! impl App for Echo {
+//!     type Payload = EchoPayload;
+//!
+//!     fn new(_node_id: NodeID, _node_ids: Vec<NodeID>) -> Self {
+//!         Self
+//!     }
+//!
+//!     async fn handle(
+//!         &mut self,
+//!         message: Message<EchoPayload>,
+//!         writer: &MessageWriter,
+//!     ) -> anyhow::Result<()> {
+//!         if let EchoPayload::Echo { echo } = &message.body.payload {
+//!             writer.reply_to(&message, EchoPayload::EchoOk { echo: echo.clone() })?;
+//!         }
+//!         Ok(())
+//!     }
+//!
+//!     async fn tick(&mut self, _writer: &MessageWriter) -> anyhow::Result<()> {
+//!         Ok(())
+//!     }
+//! }
+//!
+//! Simulation::<Echo>::run(SimConfig::default(), |sim| async move {
+//!     let client = sim.client();
+//!     let echo = EchoPayload::Echo { echo: "hi".into() };
+//!     let reply = client.call(&sim.node_ids()[0], echo).await?;
+//!     assert_eq!(reply, EchoPayload::EchoOk { echo: "hi".into() });
+//!     Ok(())
+//! })?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use crate::app::{outgoing_queue, run_node, OutgoingSender, ResponseRouter};
+use crate::checker::History;
+use crate::{
+    App, InitPayload, MaelstromPayload, Message, MessageBody, MessageWriter, Metrics,
+    MetricsSnapshot, NodeID, PayloadType, QueueConfig, RawMessage,
+};
+use anyhow::Context;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+use std::fmt::Debug;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+mod faults;
+mod network;
+mod services;
+
+pub use self::faults::*;
+use self::network::Network;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimConfig {
+    /// Nodes are named n0, n1, etc.
+    pub node_count: usize,
+    pub seed: u64,
+    /// The network at the start of the simulation, change it with
+    /// `Simulation::set_network_conditions`.
+    pub network: NetworkConditions,
+    /// How long `SimClient::call` waits for a reply.
+    pub client_timeout: Duration,
+    /// The queues of every node. Messages delivered to a node whose incoming
+    /// queue is full are dropped, and `outgoing_soft_limit` isn't simulated.
+    pub queues: QueueConfig,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            node_count: 3,
+            seed: 0,
+            network: NetworkConditions::default(),
+            client_timeout: Duration::from_secs(5),
+            queues: QueueConfig::default(),
+        }
+    }
+}
+
+/// A handle to a running simulation, see `Simulation::run`.
+pub struct Simulation<TApp: App> {
+    network: Arc<Mutex<Network>>,
+    history: Arc<Mutex<History<TApp::Payload>>>,
+    network_sender: OutgoingSender,
+    node_ids: Vec<NodeID>,
+    node_metrics: Vec<Arc<Metrics>>,
+    node_shutdown_senders: Arc<Mutex<Vec<Option<oneshot::Sender<()>>>>>,
+    next_client_id: Arc<AtomicUsize>,
+    config: SimConfig,
+    _app: PhantomData<fn() -> TApp>,
+}
+
+impl<TApp: App> Clone for Simulation<TApp> {
+    fn clone(&self) -> Self {
+        Self {
+            network: self.network.clone(),
+            history: self.history.clone(),
+            network_sender: self.network_sender.clone(),
+            node_ids: self.node_ids.clone(),
+            node_metrics: self.node_metrics.clone(),
+            node_shutdown_senders: self.node_shutdown_senders.clone(),
+            next_client_id: self.next_client_id.clone(),
+            config: self.config.clone(),
+            _app: PhantomData,
+        }
+    }
+}
+
+impl<TApp> Simulation<TApp>
+where
+    TApp: App + Send + 'static,
+    TApp::Payload: 'static + Send + Clone + Serialize + DeserializeOwned + MaelstromPayload + Debug,
+{
+    /// Starts `config.node_count` nodes running `TApp` and runs `test` against
+    /// them. Fails if `test` fails or if any node stopped with an error.
+    pub fn run<F, Fut>(config: SimConfig, test: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(Simulation<TApp>) -> Fut,
+        Fut: Future<Output = anyhow::Result<()>>,
+    {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()
+            .context("Failed to build simulation runtime")?;
+        runtime.block_on(async move {
+            let (sim, node_task_handles) = Self::start(config);
+            test(sim).await?;
+
+            for (node_index, node_task_handle) in node_task_handles.into_iter().enumerate() {
+                if !node_task_handle.is_finished() {
+                    node_task_handle.abort();
+                    continue;
+                }
+                node_task_handle
+                    .await?
+                    .with_context(|| format!("Node n{node_index} failed"))?;
+            }
+            Ok(())
+        })
+    }
+
+    fn start(config: SimConfig) -> (Self, Vec<JoinHandle<anyhow::Result<()>>>) {
+        let node_ids = (0..config.node_count)
+            .map(|index| NodeID::from(format!("n{index}")))
+            .collect::<Vec<_>>();
+        let network = Arc::new(Mutex::new(Network::new(&config)));
+        // Shared by every node, so it isn't bounded like a node's own queue.
+        let (network_sender, network_receiver) = outgoing_queue(usize::MAX);
+        tokio::spawn(network::route(network.clone(), network_receiver));
+
+        let mut node_task_handles = vec![];
+        let mut node_metrics = vec![];
+        let mut node_shutdown_senders = vec![];
+        for node_id in &node_ids {
+            let (message_sender, message_receiver) = mpsc::channel(config.queues.incoming_capacity);
+            let init_message = Message {
+                src: "c0".into(),
+                dst: node_id.clone(),
+                body: MessageBody {
+                    msg_id: Some(0.into()),
+                    in_reply_to: None,
+                    payload: InitPayload::Init {
+                        node_id: node_id.clone(),
+                        node_ids: node_ids.clone(),
+                    },
+                },
+            };
+            message_sender
+                .try_send(serde_json::to_string(&init_message).expect("init serializes"))
+                .expect("receiver is alive");
+            network
+                .lock()
+                .expect("not poisoned")
+                .add_endpoint(node_id.clone(), message_sender);
+            let metrics = Arc::new(Metrics::default());
+            let rng = StdRng::seed_from_u64(network.lock().expect("not poisoned").rng().gen());
+            let (shutdown_sender, shutdown_receiver) = oneshot::channel();
+            let shutdown = async move {
+                if shutdown_receiver.await.is_err() {
+                    std::future::pending::<()>().await;
+                }
+            };
+            node_task_handles.push(tokio::spawn(run_node::<TApp>(
+                message_receiver,
+                network_sender.clone(),
+                metrics.clone(),
+                config.queues.clone(),
+                rng,
+                shutdown,
+            )));
+            node_metrics.push(metrics);
+            node_shutdown_senders.push(Some(shutdown_sender));
+        }
+
+        let sim = Self {
+            network,
+            history: Arc::new(Mutex::new(History::new())),
+            network_sender,
+            node_ids,
+            node_metrics,
+            node_shutdown_senders: Arc::new(Mutex::new(node_shutdown_senders)),
+            // c0 is the client that sent the init messages.
+            next_client_id: Arc::new(AtomicUsize::new(1)),
+            config,
+            _app: PhantomData,
+        };
+        (sim, node_task_handles)
+    }
+
+    pub fn node_ids(&self) -> &[NodeID] {
+        &self.node_ids
+    }
+
+    /// What the runtime of `node_id` has measured so far, e.g. to count the
+    /// messages sent per client request.
+    pub fn metrics(&self, node_id: &NodeID) -> Option<MetricsSnapshot> {
+        let index = self.node_ids.iter().position(|id| id == node_id)?;
+        Some(self.node_metrics[index].snapshot())
+    }
+
+    /// Shuts `node_id` down the way `SIGTERM` does for a real node: it stops
+    /// getting new messages and `App::shutdown` is called once it's done with
+    /// the ones it has.
+    pub fn shutdown_node(&self, node_id: &NodeID) {
+        let index = self
+            .node_ids
+            .iter()
+            .position(|id| id == node_id)
+            .expect("node is part of the simulation");
+        if let Some(shutdown_sender) =
+            self.node_shutdown_senders.lock().expect("not poisoned")[index].take()
+        {
+            let _ = shutdown_sender.send(());
+        }
+    }
+
+    pub fn set_network_conditions(&self, conditions: NetworkConditions) {
+        self.network.lock().expect("not poisoned").conditions = conditions;
+    }
+
+    /// Cuts every link between `side_a` and `side_b`, in both directions.
+    pub fn partition(&self, side_a: &[NodeID], side_b: &[NodeID]) {
+        self.partition_one_way(side_a, side_b);
+        self.partition_one_way(side_b, side_a);
+    }
+
+    /// Drops messages from `from` to `to`, while messages from `to` to `from`
+    /// still get through.
+    pub fn partition_one_way(&self, from: &[NodeID], to: &[NodeID]) {
+        let mut network = self.network.lock().expect("not poisoned");
+        for from in from {
+            for to in to {
+                network.block(from, to);
+            }
+        }
+    }
+
+    /// Splits the nodes into two random halves (using the simulation's seed) and
+    /// partitions them from each other. Returns the two halves.
+    pub fn partition_randomly(&self) -> (Vec<NodeID>, Vec<NodeID>) {
+        let mut node_ids = self.node_ids.clone();
+        node_ids.shuffle(self.network.lock().expect("not poisoned").rng());
+        let side_b = node_ids.split_off(node_ids.len() / 2);
+        self.partition(&node_ids, &side_b);
+        (node_ids, side_b)
+    }
+
+    pub fn heal_partitions(&self) {
+        self.network.lock().expect("not poisoned").heal_partitions();
+    }
+
+    /// Removes all partitions and stops dropping and duplicating messages.
+    pub fn heal(&self) {
+        let mut network = self.network.lock().expect("not poisoned");
+        network.heal_partitions();
+        network.conditions.drop_probability = 0.0;
+        network.conditions.duplicate_probability = 0.0;
+    }
+
+    /// Alternates between a random partition and a healed network every
+    /// `interval`, like Maelstrom's `--nemesis partition`. Abort the returned
+    /// handle to stop it (it does not heal the network when aborted).
+    pub fn spawn_partition_nemesis(&self, interval: Duration) -> JoinHandle<()> {
+        let sim = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                sim.partition_randomly();
+                tokio::time::sleep(interval).await;
+                sim.heal_partitions();
+            }
+        })
+    }
+
+    /// Every request sent by the simulation's clients so far, with their
+    /// responses. Check it with the functions in `maelstrom::checker`.
+    pub fn history(&self) -> History<TApp::Payload> {
+        self.history.lock().expect("not poisoned").clone()
+    }
+
+    /// Creates a new client (c1, c2, etc.) that can send requests to the nodes.
+    pub fn client(&self) -> SimClient<TApp::Payload> {
+        let client_id = self.next_client_id.fetch_add(1, Ordering::SeqCst);
+        let node_id = NodeID::from(format!("c{client_id}"));
+        let (message_sender, mut message_receiver) =
+            mpsc::channel::<String>(self.config.queues.incoming_capacity);
+        let rng = {
+            let mut network = self.network.lock().expect("not poisoned");
+            network.add_endpoint(node_id.clone(), message_sender);
+            StdRng::seed_from_u64(network.rng().gen())
+        };
+
+        let (writer, mut response_callback_receiver, _timer_receiver) = MessageWriter::new(
+            node_id.clone(),
+            self.network_sender.clone(),
+            Arc::new(Metrics::default()),
+            rng,
+        );
+        tokio::spawn(async move {
+            let mut response_router = ResponseRouter::default();
+            while let Some(message) = message_receiver.recv().await {
+                response_router.process_commands(&mut response_callback_receiver);
+                let message =
+                    RawMessage::parse(message).expect("network only delivers valid messages");
+                if let Some(message) = response_router.route(message) {
+                    warn!(src = %*message.src, "Client ignoring unexpected message.");
+                }
+            }
+        });
+
+        SimClient {
+            node_id,
+            writer,
+            timeout: self.config.client_timeout,
+            history: self.history.clone(),
+        }
+    }
+}
+
+/// Sends requests to simulated nodes, like Maelstrom's clients do. Every
+/// request is recorded in the simulation's history.
+pub struct SimClient<TPayload> {
+    node_id: NodeID,
+    writer: MessageWriter,
+    timeout: Duration,
+    history: Arc<Mutex<History<TPayload>>>,
+}
+
+impl<TPayload: Debug + Clone + Serialize + DeserializeOwned + PayloadType> SimClient<TPayload> {
+    /// Sends `payload` to `node_id` and waits for the reply. An `error` reply is
+    /// returned as an `ErrorPayload` error, no reply as an `RpcTimeoutError`.
+    pub async fn call(&self, node_id: &NodeID, payload: TPayload) -> anyhow::Result<TPayload> {
+        let operation_index = self.history.lock().expect("not poisoned").invoke(
+            self.node_id.clone(),
+            node_id.clone(),
+            payload.clone(),
+        );
+        let response = self
+            .writer
+            .send_and_receive_raw_with_timeout(node_id, payload, self.timeout)
+            .await?;
+        let result = match response.error() {
+            Some(error) => Err(error),
+            None => Ok(response.decode::<TPayload>()?.body.payload),
+        };
+        self.history
+            .lock()
+            .expect("not poisoned")
+            .complete(operation_index, result.clone());
+        Ok(result?)
+    }
+}
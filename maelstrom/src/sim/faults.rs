@@ -0,0 +1,95 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// How long messages take to arrive. Messages overtake each other whenever the
+/// distribution isn't constant, so this also controls reordering.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LatencyDistribution {
+    Constant(Duration),
+    /// Bounds given the wrong way around are swapped.
+    Uniform {
+        min: Duration,
+        max: Duration,
+    },
+    /// Like Maelstrom's `--latency-dist exponential`: mostly fast, with a long
+    /// tail of slow messages.
+    Exponential {
+        mean: Duration,
+    },
+}
+
+impl LatencyDistribution {
+    pub(super) fn sample(&self, rng: &mut impl Rng) -> Duration {
+        match self {
+            LatencyDistribution::Constant(latency) => *latency,
+            LatencyDistribution::Uniform { min, max } => {
+                rng.gen_range(*min.min(max)..=*min.max(max))
+            }
+            LatencyDistribution::Exponential { mean } => {
+                let uniform = rng.gen::<f64>();
+                mean.mul_f64(-(1.0 - uniform).ln())
+            }
+        }
+    }
+}
+
+/// The state of the simulated network. Drops, duplicates and partitions only
+/// apply to traffic between nodes and services, client requests and replies
+/// always get through (as with Maelstrom's nemesis).
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkConditions {
+    pub latency: LatencyDistribution,
+    /// Chance (0.0 to 1.0) that a message is lost.
+    pub drop_probability: f64,
+    /// Chance (0.0 to 1.0) that a message is delivered twice, each copy with
+    /// its own latency.
+    pub duplicate_probability: f64,
+}
+
+impl Default for NetworkConditions {
+    fn default() -> Self {
+        Self {
+            latency: LatencyDistribution::Uniform {
+                min: Duration::from_millis(1),
+                max: Duration::from_millis(5),
+            },
+            drop_probability: 0.0,
+            duplicate_probability: 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn latencies_stay_within_distribution() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let uniform = LatencyDistribution::Uniform {
+            min: Duration::from_millis(2),
+            max: Duration::from_millis(4),
+        };
+        for _ in 0..100 {
+            let latency = uniform.sample(&mut rng);
+            assert!(latency >= Duration::from_millis(2) && latency <= Duration::from_millis(4));
+        }
+        let backwards = LatencyDistribution::Uniform {
+            min: Duration::from_millis(4),
+            max: Duration::from_millis(2),
+        };
+        let latency = backwards.sample(&mut rng);
+        assert!(latency >= Duration::from_millis(2) && latency <= Duration::from_millis(4));
+
+        let exponential = LatencyDistribution::Exponential {
+            mean: Duration::from_millis(10),
+        };
+        let total = (0..1000)
+            .map(|_| exponential.sample(&mut rng))
+            .sum::<Duration>();
+        let mean = total / 1000;
+        assert!(mean > Duration::from_millis(8) && mean < Duration::from_millis(12));
+    }
+}
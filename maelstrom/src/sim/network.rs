@@ -0,0 +1,185 @@
+use super::services::Services;
+use super::{NetworkConditions, SimConfig};
+use crate::app::OutgoingReceiver;
+use crate::{Message, NodeID};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, BinaryHeap, HashMap};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::time::{timeout_at, Instant};
+use tracing::{debug, warn};
+
+/// A message on its way to `message.dst`.
+struct InFlight {
+    deliver_at: Instant,
+    /// Breaks ties between messages delivered at the same instant, in the
+    /// order they were sent.
+    sequence: u64,
+    message: Message<serde_json::Value>,
+}
+
+impl PartialEq for InFlight {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for InFlight {}
+
+impl PartialOrd for InFlight {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for InFlight {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed so that the BinaryHeap pops the earliest delivery first.
+        (other.deliver_at, other.sequence).cmp(&(self.deliver_at, self.sequence))
+    }
+}
+
+pub(super) struct Network {
+    rng: StdRng,
+    pub(super) conditions: NetworkConditions,
+    /// Messages from the first to the second node are dropped.
+    blocked_links: BTreeSet<(NodeID, NodeID)>,
+    next_sequence: u64,
+    in_flight: BinaryHeap<InFlight>,
+    endpoints: HashMap<NodeID, mpsc::Sender<String>>,
+    services: Services,
+}
+
+impl Network {
+    pub(super) fn new(config: &SimConfig) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(config.seed),
+            conditions: config.network.clone(),
+            blocked_links: BTreeSet::new(),
+            next_sequence: 0,
+            in_flight: BinaryHeap::new(),
+            endpoints: HashMap::new(),
+            services: Services::default(),
+        }
+    }
+
+    pub(super) fn add_endpoint(&mut self, node_id: NodeID, sender: mpsc::Sender<String>) {
+        self.endpoints.insert(node_id, sender);
+    }
+
+    pub(super) fn rng(&mut self) -> &mut StdRng {
+        &mut self.rng
+    }
+
+    pub(super) fn block(&mut self, from: &NodeID, to: &NodeID) {
+        self.blocked_links.insert((from.clone(), to.clone()));
+    }
+
+    pub(super) fn heal_partitions(&mut self) {
+        self.blocked_links.clear();
+    }
+
+    fn is_faultable(message: &Message<serde_json::Value>) -> bool {
+        !message.src.is_client() && !message.dst.is_client()
+    }
+
+    fn send(&mut self, message: Message<serde_json::Value>) {
+        let copies = if !Self::is_faultable(&message) {
+            1
+        } else if self
+            .rng
+            .gen_bool(self.conditions.drop_probability.clamp(0.0, 1.0))
+        {
+            0
+        } else if self
+            .rng
+            .gen_bool(self.conditions.duplicate_probability.clamp(0.0, 1.0))
+        {
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let latency = self.conditions.latency.sample(&mut self.rng);
+            self.in_flight.push(InFlight {
+                deliver_at: Instant::now() + latency,
+                sequence: self.next_sequence,
+                message: message.clone(),
+            });
+            self.next_sequence += 1;
+        }
+    }
+
+    fn next_delivery(&self) -> Option<Instant> {
+        self.in_flight.peek().map(|in_flight| in_flight.deliver_at)
+    }
+
+    fn deliver_due(&mut self) {
+        let now = Instant::now();
+        while self
+            .in_flight
+            .peek()
+            .is_some_and(|in_flight| in_flight.deliver_at <= now)
+        {
+            let message = self.in_flight.pop().expect("peeked").message;
+            self.deliver(message);
+        }
+    }
+
+    fn deliver(&mut self, message: Message<serde_json::Value>) {
+        if Self::is_faultable(&message)
+            && self
+                .blocked_links
+                .contains(&(message.src.clone(), message.dst.clone()))
+        {
+            return;
+        }
+
+        if let Some(endpoint) = self.endpoints.get(&message.dst) {
+            let line = serde_json::to_string(&message).expect("Value serializes");
+            match endpoint.try_send(line) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    warn!(dst = %*message.dst, "Dropping message to node with a full queue.");
+                }
+                Err(TrySendError::Closed(_)) => {
+                    debug!(dst = %*message.dst, "Dropping message to stopped node.");
+                }
+            }
+            return;
+        }
+
+        match self.services.handle(&message) {
+            Some(response) => self.send(response),
+            None => warn!(dst = %*message.dst, "Dropping message to unknown node."),
+        }
+    }
+}
+
+/// Moves messages written by the nodes (and clients) through the network until
+/// every writer is gone.
+pub(super) async fn route(network: Arc<Mutex<Network>>, mut network_receiver: OutgoingReceiver) {
+    loop {
+        let next_delivery = network.lock().expect("not poisoned").next_delivery();
+        let line = match next_delivery {
+            Some(next_delivery) => match timeout_at(next_delivery, network_receiver.recv()).await {
+                Ok(line) => line,
+                Err(_elapsed) => {
+                    network.lock().expect("not poisoned").deliver_due();
+                    continue;
+                }
+            },
+            None => network_receiver.recv().await,
+        };
+        let Some(line) = line else {
+            break;
+        };
+
+        match serde_json::from_str::<Message<serde_json::Value>>(&line) {
+            Ok(message) => network.lock().expect("not poisoned").send(message),
+            Err(error) => warn!(line, "Dropping unparseable message: {error}."),
+        }
+    }
+}
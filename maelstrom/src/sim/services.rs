@@ -0,0 +1,159 @@
+use crate::{
+    ErrorPayload, KVPayload, MaelstromErrorCode, Message, MessageBody, MessageID, Ts, TsOk,
+};
+use std::collections::HashMap;
+
+/// In-memory stand-ins for Maelstrom's built-in services.
+#[derive(Default)]
+pub(super) struct Services {
+    seq_kv: KvStore,
+    lin_kv: KvStore,
+    lww_kv: KvStore,
+    lin_tso: Tso,
+    next_msg_id: u32,
+}
+
+impl Services {
+    /// Returns the service's response, or None if `message.dst` isn't a
+    /// service.
+    pub(super) fn handle(
+        &mut self,
+        message: &Message<serde_json::Value>,
+    ) -> Option<Message<serde_json::Value>> {
+        let payload = match &**message.dst {
+            "seq-kv" => self.seq_kv.handle(&message.body.payload),
+            "lin-kv" => self.lin_kv.handle(&message.body.payload),
+            "lww-kv" => self.lww_kv.handle(&message.body.payload),
+            "lin-tso" => self.lin_tso.handle(&message.body.payload),
+            _ => return None,
+        };
+        let msg_id = MessageID(self.next_msg_id);
+        self.next_msg_id += 1;
+        Some(Message {
+            src: message.dst.clone(),
+            dst: message.src.clone(),
+            body: MessageBody {
+                msg_id: Some(msg_id),
+                in_reply_to: message.body.msg_id,
+                payload,
+            },
+        })
+    }
+}
+
+/// A linearizable key-value store: lin-kv, and a valid (if lucky) seq-kv and
+/// lww-kv.
+#[derive(Default)]
+struct KvStore {
+    /// Keyed by the key's JSON, since keys can be any JSON value.
+    values: HashMap<String, serde_json::Value>,
+}
+
+impl KvStore {
+    fn handle(&mut self, payload: &serde_json::Value) -> serde_json::Value {
+        let response = match serde_json::from_value::<KVPayload<serde_json::Value, serde_json::Value>>(
+            payload.clone(),
+        ) {
+            Ok(request) => self.apply(request),
+            Err(error) => Err(ErrorPayload::new(
+                MaelstromErrorCode::NotSupported,
+                error.to_string(),
+            )),
+        };
+        match response {
+            Ok(payload) => serde_json::to_value(payload),
+            Err(error) => serde_json::to_value(error),
+        }
+        .expect("payloads serialize")
+    }
+
+    fn apply(
+        &mut self,
+        request: KVPayload<serde_json::Value, serde_json::Value>,
+    ) -> Result<KVPayload<(), serde_json::Value>, ErrorPayload> {
+        match request {
+            KVPayload::Read { key } => match self.values.get(&key.to_string()) {
+                Some(value) => Ok(KVPayload::ReadOk {
+                    value: value.clone(),
+                }),
+                None => Err(ErrorPayload::new(
+                    MaelstromErrorCode::KeyDoesNotExist,
+                    "key does not exist",
+                )),
+            },
+            KVPayload::Write { key, value } => {
+                self.values.insert(key.to_string(), value);
+                Ok(KVPayload::WriteOk)
+            }
+            KVPayload::CompareAndSet {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => match self.values.get(&key.to_string()) {
+                None if create_if_not_exists == Some(true) => {
+                    self.values.insert(key.to_string(), to);
+                    Ok(KVPayload::CompareAndSetOk)
+                }
+                None => Err(ErrorPayload::new(
+                    MaelstromErrorCode::KeyDoesNotExist,
+                    "key does not exist",
+                )),
+                Some(current) if *current != from => Err(ErrorPayload::new(
+                    MaelstromErrorCode::PreconditionFailed,
+                    format!("expected {from}, but had {current}"),
+                )),
+                Some(_) => {
+                    self.values.insert(key.to_string(), to);
+                    Ok(KVPayload::CompareAndSetOk)
+                }
+            },
+            _ => Err(ErrorPayload::new(
+                MaelstromErrorCode::NotSupported,
+                "not a kv request",
+            )),
+        }
+    }
+}
+
+/// A timestamp oracle, counting up from 0.
+#[derive(Default)]
+struct Tso {
+    next_ts: u64,
+}
+
+impl Tso {
+    fn handle(&mut self, payload: &serde_json::Value) -> serde_json::Value {
+        match serde_json::from_value::<Ts>(payload.clone()) {
+            Ok(Ts {}) => {
+                let ts = self.next_ts;
+                self.next_ts += 1;
+                serde_json::to_value(TsOk { ts })
+            }
+            Err(_) => serde_json::to_value(ErrorPayload::new(
+                MaelstromErrorCode::NotSupported,
+                "not a ts request",
+            )),
+        }
+        .expect("payloads serialize")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tso_hands_out_increasing_timestamps() {
+        let mut tso = Tso::default();
+        let request = serde_json::to_value(Ts {}).unwrap();
+        let timestamps = (0..3)
+            .map(|_| {
+                serde_json::from_value::<TsOk>(tso.handle(&request))
+                    .unwrap()
+                    .ts
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(timestamps, vec![0, 1, 2]);
+    }
+}
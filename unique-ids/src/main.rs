@@ -1,8 +1,8 @@
+use tracing::warn;
 use uuid::Uuid;
 
-#[derive(Debug, PartialEq, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
-#[serde(tag = "type")]
-#[serde(rename_all = "snake_case")]
+#[maelstrom::payload]
+#[derive(Debug, PartialEq, Clone)]
 enum UniqueIdsPayload {
     Generate,
     GenerateOk { id: Uuid },
@@ -31,7 +31,7 @@ impl maelstrom::App for UniqueIds {
                 )?;
             }
             _ => {
-                eprintln!("Ignoring non-relevant payload: {message:?}.");
+                warn!(?message, "Ignoring non-relevant payload.");
                 return Ok(());
             }
         }
@@ -48,3 +48,27 @@ impl maelstrom::App for UniqueIds {
 async fn main() -> anyhow::Result<()> {
     maelstrom::event_loop::<UniqueIds, UniqueIdsPayload>().await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maelstrom::sim::{SimConfig, Simulation};
+    use std::collections::HashSet;
+
+    #[test]
+    fn generated_ids_are_unique_across_nodes() -> anyhow::Result<()> {
+        Simulation::<UniqueIds>::run(SimConfig::default(), |sim| async move {
+            let client = sim.client();
+            let mut ids = HashSet::new();
+            for node_id in sim.node_ids().iter().cycle().take(100) {
+                let UniqueIdsPayload::GenerateOk { id } =
+                    client.call(node_id, UniqueIdsPayload::Generate).await?
+                else {
+                    anyhow::bail!("Expected GenerateOk in response to Generate.");
+                };
+                assert!(ids.insert(id), "{id} was generated twice");
+            }
+            Ok(())
+        })
+    }
+}